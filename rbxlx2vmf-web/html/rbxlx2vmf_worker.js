@@ -22,13 +22,22 @@ onmessage = async (e) => {
 
         wasm_bindgen.convert_map(
             'map.vmf',
-            e.data.file,
+            new Uint8Array(e.data.file),
             e.data.is_texture_output_enabled,
             e.data.use_developer_textures,
+            e.data.color_mode,
+            e.data.color_tolerance,
             e.data.map_scale,
             e.data.auto_skybox_enabled,
             e.data.skybox_clearance,
             e.data.optimization_enabled,
+            e.data.cylinder_sides,
+            e.data.sphere_mode,
+            e.data.displacement_power,
+            e.data.sphere_segments,
+            e.data.truss_mode,
+            e.data.surface_light_mode,
+            e.data.hinge_knobs_enabled,
             e.data.skyname,
             e.data.web_origin
         )
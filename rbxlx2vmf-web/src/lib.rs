@@ -10,7 +10,7 @@ use wasm_bindgen::prelude::*;
 use zip::write::FileOptions;
 use zip::{CompressionMethod, ZipWriter};
 use rbxlx2vmf::conv;
-use rbxlx2vmf::conv::{ConvertOptions, OwnedOrMut, OwnedOrRef};
+use rbxlx2vmf::conv::{ColorMode, ConvertOptions, Game, OwnedOrMut, OwnedOrRef, SphereMode, SurfaceLightMode, TrussMode, MAX_CYLINDER_SIDES, MAX_SPHERE_SEGMENTS, MIN_SPHERE_SEGMENTS};
 
 // Use `wee_alloc` as the global allocator for WASM
 #[global_allocator]
@@ -73,16 +73,23 @@ impl Drop for WebLogger {
 struct JSConvertOptions<'a> {
     print_buffer: Rc<RefCell<Vec<u8>>>,
     input_name: &'a str,
-    input_data: String,
+    input_data: Vec<u8>,
     zip_writer: ZipWriter<Cursor<&'a mut Vec<u8>>>,
     is_texture_output_enabled: bool,
     use_developer_textures: bool,
+    color_mode: ColorMode,
     map_scale: f64,
     auto_skybox_enabled: bool,
     skybox_clearance: f64,
     optimization_enabled: bool,
     decal_size: u64,
-    skybox_name: &'a str
+    cylinder_sides: u32,
+    sphere_mode: SphereMode,
+    truss_mode: TrussMode,
+    surface_light_mode: SurfaceLightMode,
+    hinge_knobs_enabled: bool,
+    skybox_name: &'a str,
+    game: Game
 }
 
 impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions<'a> {
@@ -97,7 +104,7 @@ impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions
         &self.input_name
     }
 
-    fn read_input_data(&self) -> OwnedOrRef<'_, String> {
+    fn read_input_data(&self) -> OwnedOrRef<'_, Vec<u8>> {
         OwnedOrRef::Ref(&self.input_data)
     }
 
@@ -113,6 +120,14 @@ impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions
         OwnedOrMut::Ref(&mut self.zip_writer)
     }
 
+    fn read_asset(&self, _id: u64) -> Option<Vec<u8>> {
+        None    // The browser version has no access to local files
+    }
+
+    fn has_local_assets(&self) -> bool {
+        false
+    }
+
     fn texture_output_enabled(&self) -> bool {
         self.is_texture_output_enabled
     }
@@ -121,6 +136,14 @@ impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions
         self.use_developer_textures
     }
 
+    fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    fn surface_prop_overrides(&self) -> &[(String, String)] {
+        &[]
+    }
+
     fn map_scale(&self) -> f64 {
         self.map_scale
     }
@@ -137,6 +160,62 @@ impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions
         self.optimization_enabled
     }
 
+    fn cylinder_sides(&self) -> u32 {
+        self.cylinder_sides
+    }
+
+    fn sphere_mode(&self) -> SphereMode {
+        self.sphere_mode
+    }
+
+    fn truss_mode(&self) -> TrussMode {
+        self.truss_mode
+    }
+
+    fn surface_light_mode(&self) -> SurfaceLightMode {
+        self.surface_light_mode
+    }
+
+    fn hinge_knobs_enabled(&self) -> bool {
+        self.hinge_knobs_enabled
+    }
+
+    fn included_paths(&self) -> &[String] {
+        &[]
+    }
+
+    fn include_tools(&self) -> bool {
+        false
+    }
+
+    fn include_characters(&self) -> bool {
+        false
+    }
+
+    fn included_path_patterns(&self) -> &[String] {
+        &[]
+    }
+
+    fn included_classes(&self) -> &[String] {
+        &[]
+    }
+
+    fn included_names(&self) -> &[String] {
+        &[]
+    }
+
+    fn excluded_paths(&self) -> &[String] {
+        &[]
+    }
+
+    fn excluded_classes(&self) -> &[String] {
+        &[]
+    }
+
+    fn excluded_names(&self) -> &[String] {
+        &[]
+    }
+
     fn decal_size(&self) -> u64 {
         self.decal_size
     }
@@ -144,18 +223,31 @@ impl<'a> ConvertOptions<ZipWriter<Cursor<&'a mut Vec<u8>>>> for JSConvertOptions
     fn skybox_name(&self) -> &str {
         self.skybox_name
     }
+
+    fn game(&self) -> Game {
+        self.game
+    }
 }
 
 #[wasm_bindgen]
 pub async fn convert_map(
     input_name: String,
-    input_data: String,
+    input_data: Vec<u8>,
     is_texture_output_enabled: bool,
     use_developer_textures: bool,
+    color_mode: String,
+    color_tolerance: u8,
     map_scale: f64,
     auto_skybox_enabled: bool,
     skybox_clearance: f64,
     optimization_enabled: bool,
+    cylinder_sides: u32,
+    sphere_mode: String,
+    displacement_power: u32,
+    sphere_segments: u32,
+    truss_mode: String,
+    surface_light_mode: String,
+    hinge_knobs_enabled: bool,
     skyname: String
 ) -> Result<Uint8Array, JsValue> {
     let mut zip_buffer = Vec::new();
@@ -169,11 +261,30 @@ pub async fn convert_map(
         zip_writer,
         is_texture_output_enabled,
         use_developer_textures,
+        color_mode: match &*color_mode {
+            "baked" => ColorMode::Baked { tolerance: color_tolerance },
+            _ => ColorMode::Tinted
+        },
         map_scale,
         auto_skybox_enabled,
         skybox_clearance,
         optimization_enabled,
         decal_size: 256,
+        cylinder_sides: cylinder_sides.clamp(3, MAX_CYLINDER_SIDES),
+        sphere_mode: match &*sphere_mode {
+            "polyhedron" => SphereMode::Polyhedron { segments: sphere_segments.clamp(MIN_SPHERE_SEGMENTS, MAX_SPHERE_SEGMENTS) },
+            "skip" => SphereMode::Skip,
+            _ => SphereMode::Displacement { power: displacement_power.clamp(2, 4) }
+        },
+        truss_mode: match &*truss_mode {
+            "textured" => TrussMode::Textured,
+            _ => TrussMode::Lattice
+        },
+        surface_light_mode: match &*surface_light_mode {
+            "selfillum" => SurfaceLightMode::SelfIllum,
+            _ => SurfaceLightMode::Lights
+        },
+        hinge_knobs_enabled,
         skybox_name: match &*skyname {
             "css" => "sky_day01_05",
             "csgo" => "sky_day02_05",
@@ -189,7 +300,8 @@ pub async fn convert_map(
             "portal" => "sky_day01_05_hdr",
             "tf2" => "sky_day01_01",
             _ => "default_skybox_fixme" // The only guard against invalid values here is HTML form validation, but as we're a clientside application, just substitute in a placeholder value
-        }
+        },
+        game: Game::from_id(&*skyname).unwrap_or(Game::Hl2)  // As above, HL2 is used as a placeholder as it only emits generic entities, such as info_player_start
     }).await;
     match result {
         Ok(0) => {
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use crate::rbx::{CFrame, Color3, Instance, InstanceTree, Property, Vector3};
+
+// Binary format constants, see https://dom.rojo.space/binary
+const MAGIC: &[u8] = b"<roblox!";
+const SIGNATURE: &[u8] = b"\x89\xff\r\n\x1a\n";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const HEADER_SIZE: usize = 32;
+/// Largest uncompressed chunk size read, guarding against allocations from corrupt chunk headers
+const MAX_CHUNK_SIZE: usize = 256 * 1024 * 1024;
+/// LZ4 compresses data by a factor of at most 255
+const MAX_LZ4_RATIO: usize = 255;
+
+/// Returns true if the given data is a binary format (*.rbxl, *.rbxm) file, rather than an XML format file
+pub fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[derive(Debug)]
+pub enum BinaryError {
+    InvalidHeader,
+    UnexpectedEof,
+    Decompression(String),
+    InvalidChunkSize(usize),
+    UnknownReferent(i32),
+    InvalidParent(i32),
+}
+
+impl Display for BinaryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryError::InvalidHeader => write!(f, "invalid file header"),
+            BinaryError::UnexpectedEof => write!(f, "unexpected end of file"),
+            BinaryError::Decompression(error) => write!(f, "could not decompress chunk: {}", error),
+            BinaryError::InvalidChunkSize(size) => write!(f, "invalid chunk size {}", size),
+            BinaryError::UnknownReferent(referent) => write!(f, "unknown instance referent {}", referent),
+            BinaryError::InvalidParent(referent) => write!(f, "invalid parent for instance referent {}", referent),
+        }
+    }
+}
+
+/// Reads the instance tree of a binary place or model file
+pub fn parse_binary(data: &[u8]) -> Result<InstanceTree, BinaryError> {
+    if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) || !data[MAGIC.len()..].starts_with(SIGNATURE) {
+        return Err(BinaryError::InvalidHeader);
+    }
+    let mut reader = ByteReader { data: &data[HEADER_SIZE..] };
+
+    let mut tree = InstanceTree::new();
+    let mut classes: HashMap<u32, Vec<usize>> = HashMap::new();   // Class ID -> Instance indices
+    let mut referents: HashMap<i32, usize> = HashMap::new();      // Referent -> Instance index
+
+    while !reader.data.is_empty() {
+        let name = reader.bytes(4)?;
+        let compressed_length = reader.u32()? as usize;
+        let uncompressed_length = reader.u32()? as usize;
+        reader.bytes(4)?;   // Reserved
+
+        let chunk_data = if compressed_length == 0 {
+            reader.bytes(uncompressed_length)?.to_vec()
+        } else {
+            let compressed = reader.bytes(compressed_length)?;
+            let is_zstd = compressed.starts_with(ZSTD_MAGIC);
+            // The uncompressed size is read from the file, and is checked before allocating for it
+            if uncompressed_length > MAX_CHUNK_SIZE || (!is_zstd && uncompressed_length > compressed_length.saturating_mul(MAX_LZ4_RATIO)) {
+                return Err(BinaryError::InvalidChunkSize(uncompressed_length));
+            }
+            let buffer = if is_zstd {
+                let mut buffer = Vec::with_capacity(uncompressed_length);
+                ruzstd::decoding::StreamingDecoder::new(compressed)
+                    .map_err(|error| BinaryError::Decompression(error.to_string()))?
+                    .take(uncompressed_length as u64 + 1)  // One byte past the expected size, to detect chunks that are too large
+                    .read_to_end(&mut buffer)
+                    .map_err(|error| BinaryError::Decompression(error.to_string()))?;
+                buffer
+            } else {
+                lz4_flex::block::decompress(compressed, uncompressed_length)
+                    .map_err(|error| BinaryError::Decompression(error.to_string()))?
+            };
+            if buffer.len() != uncompressed_length {
+                return Err(BinaryError::Decompression(format!("expected {} bytes, found {}", uncompressed_length, buffer.len())));
+            }
+            buffer
+        };
+        let mut chunk = ByteReader { data: &chunk_data };
+
+        match name {
+            b"INST" => {
+                let class_id = chunk.u32()?;
+                let class_name = chunk.string()?;
+                chunk.u8()?;    // Object format, 1 for services
+                let count = chunk.u32()? as usize;
+                let class_instances = classes.entry(class_id).or_default();
+                for referent in chunk.referents(count)? {
+                    let index = tree.insert(Instance::new(class_name.clone(), format!("RBX{}", referent)), None);
+                    referents.insert(referent, index);
+                    class_instances.push(index);
+                }
+            }
+            b"PROP" => {
+                let class_id = chunk.u32()?;
+                let property_name = chunk.string()?;
+                let type_id = chunk.u8()?;
+                if let Some(class_instances) = classes.get(&class_id) {
+                    if let Some(values) = chunk.values(type_id, class_instances.len())? {
+                        for (index, value) in class_instances.iter().zip(values) {
+                            let instance = tree.get_mut(*index);
+                            match (&*property_name, value) {
+                                ("Name", Property::String(name)) => instance.name = name,
+                                (_, value) => { instance.properties.insert(property_name.clone(), value); }
+                            }
+                        }
+                    }
+                }
+            }
+            b"PRNT" => {
+                chunk.u8()?;    // Version
+                let count = chunk.u32()? as usize;
+                let children = chunk.referents(count)?;
+                let parents = chunk.referents(count)?;
+                for (child, parent) in children.into_iter().zip(parents) {
+                    let child_index = *referents.get(&child).ok_or(BinaryError::UnknownReferent(child))?;
+                    if parent != -1 {
+                        let parent_index = *referents.get(&parent).ok_or(BinaryError::UnknownReferent(parent))?;
+                        // Instances are parented at most once, and never to themselves or their descendants, which would form a cycle
+                        if tree.get(child_index).parent.is_some() || tree.is_ancestor(child_index, parent_index) {
+                            return Err(BinaryError::InvalidParent(child));
+                        }
+                        tree.set_parent(child_index, Some(parent_index));
+                    }
+                }
+            }
+            b"END\0" => break,
+            _ => {}     // META, SSTR and SIGN chunks carry no geometry
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Cursor over little-endian binary data
+struct ByteReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], BinaryError> {
+        if self.data.len() < length {
+            return Err(BinaryError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, BinaryError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BinaryError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, BinaryError> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, BinaryError> {
+        let length = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).into_owned())
+    }
+
+    /// Reads an array of big-endian values, which are stored with their bytes interleaved; All first bytes, followed by all second bytes, etc.
+    fn interleaved<const N: usize>(&mut self, count: usize) -> Result<Vec<[u8; N]>, BinaryError> {
+        let bytes = self.bytes(count.checked_mul(N).ok_or(BinaryError::UnexpectedEof)?)?;
+        Ok(
+            (0..count)
+                .map(|index| std::array::from_fn(|byte| bytes[byte * count + index]))
+                .collect()
+        )
+    }
+
+    fn u32_array(&mut self, count: usize) -> Result<Vec<u32>, BinaryError> {
+        Ok(self.interleaved::<4>(count)?.into_iter().map(u32::from_be_bytes).collect())
+    }
+
+    /// Interleaved array of zigzag-encoded integers
+    fn i32_array(&mut self, count: usize) -> Result<Vec<i32>, BinaryError> {
+        Ok(self.u32_array(count)?.into_iter().map(|value| ((value >> 1) as i32) ^ -((value & 1) as i32)).collect())
+    }
+
+    /// Interleaved array of floats, with the sign bit rotated to the least significant bit
+    fn float_array(&mut self, count: usize) -> Result<Vec<f32>, BinaryError> {
+        Ok(self.u32_array(count)?.into_iter().map(|value| f32::from_bits(value.rotate_right(1))).collect())
+    }
+
+    /// Referents are stored as the difference from the previous referent
+    fn referents(&mut self, count: usize) -> Result<Vec<i32>, BinaryError> {
+        let mut referents = self.i32_array(count)?;
+        for index in 1..referents.len() {
+            referents[index] = referents[index].wrapping_add(referents[index - 1]);
+        }
+        Ok(referents)
+    }
+
+    fn vector3_array(&mut self, count: usize) -> Result<Vec<Vector3>, BinaryError> {
+        let x = self.float_array(count)?;
+        let y = self.float_array(count)?;
+        let z = self.float_array(count)?;
+        Ok((0..count).map(|index| Vector3 { x: x[index] as f64, y: y[index] as f64, z: z[index] as f64 }).collect())
+    }
+
+    /// Reads an array of property values, returns None if the type is not supported
+    fn values(&mut self, type_id: u8, count: usize) -> Result<Option<Vec<Property>>, BinaryError> {
+        Ok(Some(match type_id {
+            0x01 => (0..count).map(|_| self.string().map(Property::String)).collect::<Result<_, _>>()?,
+            0x02 => self.bytes(count)?.iter().map(|byte| Property::Bool(*byte != 0)).collect(),
+            0x03 => self.i32_array(count)?.into_iter().map(|int| Property::Int(int as i64)).collect(),
+            0x04 => self.float_array(count)?.into_iter().map(|float| Property::Float(float as f64)).collect(),
+            0x05 => (0..count).map(|_| self.bytes(8).map(|bytes| Property::Float(f64::from_le_bytes(bytes.try_into().unwrap())))).collect::<Result<_, _>>()?,
+            0x0B => self.u32_array(count)?.into_iter().map(|brick_color| Property::Int(brick_color as i64)).collect(),  // BrickColor, stored as int in the XML format
+            0x0C => {
+                let red = self.float_array(count)?;
+                let green = self.float_array(count)?;
+                let blue = self.float_array(count)?;
+                (0..count).map(|index| Property::Color3(Color3::from_floats(red[index] as f64, green[index] as f64, blue[index] as f64))).collect()
+            }
+            0x0E => self.vector3_array(count)?.into_iter().map(Property::Vector3).collect(),
+            0x10 => {
+                let mut rotations = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let rotation_id = self.u8()?;
+                    rotations.push(if rotation_id == 0 {
+                        let mut rows = [[0.0; 3]; 3];
+                        for row in &mut rows {
+                            for value in row.iter_mut() {
+                                *value = self.f32()? as f64;
+                            }
+                        }
+                        // Stored row-major, rot_matrix holds columns
+                        [
+                            [rows[0][0], rows[1][0], rows[2][0]],
+                            [rows[0][1], rows[1][1], rows[2][1]],
+                            [rows[0][2], rows[1][2], rows[2][2]],
+                        ]
+                    } else {
+                        basic_rotation(rotation_id)
+                    });
+                }
+                let positions = self.vector3_array(count)?;
+                rotations.into_iter()
+                    .zip(positions)
+                    .map(|(rot_matrix, position)| Property::CoordinateFrame(CFrame { position, rot_matrix }))
+                    .collect()
+            }
+            0x12 => self.u32_array(count)?.into_iter().map(Property::Token).collect(),
+            0x1A => {
+                let red = self.bytes(count)?;
+                let green = self.bytes(count)?;
+                let blue = self.bytes(count)?;
+                (0..count).map(|index| Property::Color3uint8(Color3 { red: red[index], green: green[index], blue: blue[index] })).collect()
+            }
+            _ => return Ok(None)
+        }))
+    }
+}
+
+/// Axis-aligned rotations are stored as an ID, which encodes the right and up vectors as NormalIds
+fn basic_rotation(id: u8) -> [[f64; 3]; 3] {
+    const NORMALS: [[f64; 3]; 6] = [
+        [1.0, 0.0, 0.0],    // Right
+        [0.0, 1.0, 0.0],    // Top
+        [0.0, 0.0, 1.0],    // Back
+        [-1.0, 0.0, 0.0],   // Left
+        [0.0, -1.0, 0.0],   // Bottom
+        [0.0, 0.0, -1.0],   // Front
+    ];
+    let index = id.saturating_sub(1) as usize;
+    let right = NORMALS[(index / 6) % 6];
+    let up = NORMALS[index % 6];
+    let back = [
+        right[1] * up[2] - right[2] * up[1],
+        right[2] * up[0] - right[0] * up[2],
+        right[0] * up[1] - right[1] * up[0],
+    ];
+    [right, up, back]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Workspace with two parts; "A" at (0, 5, 0) with a basic rotation, "B" at (10, 0, -3) rotated 90° with a full matrix
+    const FIXTURES: [(&str, &[u8]); 3] = [
+        ("uncompressed", include_bytes!("../../tests/fixtures/parts_uncompressed.rbxl")),
+        ("lz4", include_bytes!("../../tests/fixtures/parts_lz4.rbxl")),
+        ("zstd", include_bytes!("../../tests/fixtures/parts_zstd.rbxl")),
+    ];
+
+    #[test]
+    fn parses_fixtures() {
+        for (name, data) in FIXTURES {
+            assert!(is_binary(data), "{}", name);
+            let tree = parse_binary(data).unwrap_or_else(|error| panic!("{}: {}", name, error));
+
+            let roots = tree.roots().collect::<Vec<_>>();
+            assert_eq!(roots.len(), 1, "{}", name);
+            let workspace = tree.get(roots[0]);
+            assert_eq!(workspace.class_name, "Workspace", "{}", name);
+
+            let parts = tree.children(roots[0]).collect::<Vec<_>>();
+            assert_eq!(parts.iter().map(|part| (&*part.class_name, &*part.name)).collect::<Vec<_>>(), [("Part", "A"), ("Part", "B")], "{}", name);
+            let (a, b) = (parts[0], parts[1]);
+
+            assert_eq!(a.get_vector3("size"), Some(Vector3 { x: 4.0, y: 1.0, z: 2.0 }), "{}", name);
+            assert_eq!(b.get_vector3("size"), Some(Vector3 { x: 2.0, y: 1.0, z: 3.0 }), "{}", name);
+            assert_eq!(
+                a.get_cframe("CFrame"),
+                Some(CFrame { position: Vector3 { x: 0.0, y: 5.0, z: 0.0 }, rot_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] }),
+                "{}", name
+            );
+            assert_eq!(
+                b.get_cframe("CFrame"),
+                Some(CFrame { position: Vector3 { x: 10.0, y: 0.0, z: -3.0 }, rot_matrix: [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]] }),
+                "{}", name
+            );
+            assert_eq!(a.properties.get("Color3uint8"), Some(&Property::Color3uint8(Color3 { red: 255, green: 0, blue: 128 })), "{}", name);
+            assert_eq!(b.properties.get("Color3uint8"), Some(&Property::Color3uint8(Color3 { red: 10, green: 20, blue: 30 })), "{}", name);
+            assert_eq!(b.get_float("Transparency"), Some(0.5), "{}", name);
+            assert_eq!((a.get_token("Material"), b.get_token("Material")), (Some(256), Some(512)), "{}", name);
+            assert_eq!((a.get_token("TopSurface"), b.get_token("TopSurface")), (Some(3), Some(0)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(matches!(parse_binary(b"<roblox xmlns:xmime=\"http://www.w3.org/2005/05/xmlmime\" version=\"4\">"), Err(BinaryError::InvalidHeader)));
+        let (_, data) = FIXTURES[0];
+        assert!(matches!(parse_binary(&data[..data.len() - 20]), Err(BinaryError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        // Uncompressed size of the first chunk, following the chunk name and compressed size
+        const SIZE_OFFSET: usize = HEADER_SIZE + 8;
+        let with_size = |data: &[u8], change: &dyn Fn(u32) -> u32| {
+            let mut data = data.to_vec();
+            let size = u32::from_le_bytes(data[SIZE_OFFSET..SIZE_OFFSET + 4].try_into().unwrap());
+            data[SIZE_OFFSET..SIZE_OFFSET + 4].copy_from_slice(&change(size).to_le_bytes());
+            data
+        };
+        for (name, data) in &FIXTURES[1..] {
+            assert!(matches!(parse_binary(&with_size(data, &|_| u32::MAX)), Err(BinaryError::InvalidChunkSize(_))), "{}", name);
+            assert!(matches!(parse_binary(&with_size(data, &|size| size + 1)), Err(BinaryError::Decompression(_))), "{}", name);
+            assert!(matches!(parse_binary(&with_size(data, &|size| size - 1)), Err(BinaryError::Decompression(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_parents() {
+        // PRNT chunk of the uncompressed fixture, with 3 referents for Workspace (0) and parts A (1) and B (2)
+        let with_parents = |children: [i32; 3], parents: [i32; 3]| {
+            let (_, data) = FIXTURES[0];
+            let mut data = data.to_vec();
+            let offset = data.windows(4).position(|name| name == b"PRNT").unwrap() + 16 + 5;   // Chunk header, version and count
+            for (array, referents) in [children, parents].into_iter().enumerate() {
+                let mut previous = 0;
+                for (index, referent) in referents.into_iter().enumerate() {
+                    let delta = referent.wrapping_sub(previous);
+                    previous = referent;
+                    let zigzag = ((delta << 1) ^ (delta >> 31)) as u32;
+                    for (byte, value) in zigzag.to_be_bytes().into_iter().enumerate() {
+                        data[offset + array * 12 + byte * 3 + index] = value;
+                    }
+                }
+            }
+            data
+        };
+        assert_eq!(parse_binary(&with_parents([0, 1, 2], [-1, 0, 0])).unwrap().roots().count(), 1);
+        // Parented to itself
+        assert!(matches!(parse_binary(&with_parents([0, 1, 2], [-1, 1, 0])), Err(BinaryError::InvalidParent(1))));
+        // Parented twice
+        assert!(matches!(parse_binary(&with_parents([0, 1, 1], [-1, 0, 2])), Err(BinaryError::InvalidParent(1))));
+        // Cycle of Workspace -> B -> A -> Workspace
+        assert!(matches!(parse_binary(&with_parents([0, 1, 2], [2, 0, 1])), Err(BinaryError::InvalidParent(2))));
+    }
+
+    #[test]
+    fn decodes_interleaved_arrays() {
+        // Zigzag integers 0, 1, 2, 3, 4 decode to 0, -1, 1, -2, 2
+        assert_eq!(ByteReader { data: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4] }.i32_array(5).unwrap(), [0, -1, 1, -2, 2]);
+        // Referents are differences from the previous referent
+        assert_eq!(ByteReader { data: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 6, 2, 3] }.referents(3).unwrap(), [3, 4, 2]);
+        // Floats have their sign bit rotated to the least significant bit
+        let floats = [1.5f32, -2.25, 0.0];
+        let mut data = vec![0; 12];
+        for (index, float) in floats.iter().enumerate() {
+            for (byte, value) in float.to_bits().rotate_left(1).to_be_bytes().into_iter().enumerate() {
+                data[byte * floats.len() + index] = value;
+            }
+        }
+        assert_eq!(ByteReader { data: &data }.float_array(3).unwrap(), floats);
+        assert!(matches!(ByteReader { data: &[0; 7] }.u32_array(2), Err(BinaryError::UnexpectedEof)));
+        assert!(matches!(ByteReader { data: &[0; 8] }.u32_array(usize::MAX / 2), Err(BinaryError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn basic_rotations_are_right_handed() {
+        assert_eq!(basic_rotation(0x02), [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+        // Right vector pointing back, up vector pointing up; A 90° turn around the Y axis
+        assert_eq!(basic_rotation(0x0E), [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]]);
+        for id in 1..=36u8 {
+            let [right, up, back] = basic_rotation(id);
+            let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+            if dot(right, up) == 0.0 {
+                let determinant = dot(back, [
+                    right[1] * up[2] - right[2] * up[1],
+                    right[2] * up[0] - right[0] * up[2],
+                    right[0] * up[1] - right[1] * up[0],
+                ]);
+                assert_eq!((dot(back, back), determinant), (1.0, 1.0), "rotation id {}", id);
+            }
+        }
+    }
+}
@@ -1,21 +1,31 @@
+pub mod binary;
 pub mod parse;
 pub mod texture;
 
+use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::{Write};
+use image::RgbaImage;
+use image::imageops::FilterType;
 use roxmltree::Document;
 use crate::conv::texture::RobloxTexture;
-use crate::rbx::{BoundingBox, Material, Part, PartShape};
-use crate::vmf::{Solid, TextureMap, VMFBuilder, VMFTexture};
-use crate::rbx::{Vector3, CFrame, PartType, Color3};
+use crate::rbx::{BoundingBox, InstanceTree, Material, Part, PartShape};
+use crate::vmf::{Entity, Solid, TextureMap, VMFBuilder, VMFTexture};
+use crate::rbx::{Vector3, CFrame, PartType, Color3, Light, LightType, Lighting, Sky};
 use crate::conv::texture::TextureScale;
 use crate::vmf::{Side, TextureFace, Displacement};
+use crate::vtf;
 
 
 // VMF format limitations
 const MAX_BRUSH_COUNT: usize = 8192;
 const MAX_MAP_SIZE: f64 = 32768.0;
 const ID_BLOCK_SIZE: u32 = 35000;
+/// Brushes can have at most 128 sides, cylinders have two sides for their ends
+pub const MAX_CYLINDER_SIDES: u32 = 126;
+/// Sphere polyhedrons have 2 + segments * (segments / 2 - 1) sides, which is at most 128 sides for up to 16 segments
+pub const MIN_SPHERE_SEGMENTS: u32 = 4;
+pub const MAX_SPHERE_SEGMENTS: u32 = 16;
 
 /// AsRef variant with explicit lifetime
 #[allow(unused)]    // We use one variant at a time in the binary and wasm
@@ -49,26 +59,193 @@ impl<'a, T> OwnedOrMut<'a, T> {
     }
 }
 
+/// Conversion method for spherical parts
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SphereMode {
+    /// Cuboid brush with faces displaced onto the sphere; Displacements cannot be func_detail
+    Displacement { power: u32 },
+    /// Convex polyhedron brush, with segments around its vertical axis
+    Polyhedron { segments: u32 },
+    Skip,
+}
+
+impl Display for SphereMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SphereMode::Displacement { power } => write!(f, "DISPLACEMENT (power {})", power),
+            SphereMode::Polyhedron { segments } => write!(f, "POLYHEDRON ({} segments)", segments),
+            SphereMode::Skip => write!(f, "SKIP"),
+        }
+    }
+}
+
+/// Conversion method for truss parts
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TrussMode {
+    /// Detail brushes for the rails and rungs of the truss
+    Lattice,
+    /// Detail box with an alpha-tested truss texture
+    Textured,
+}
+
+impl Display for TrussMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrussMode::Lattice => write!(f, "LATTICE"),
+            TrussMode::Textured => write!(f, "TEXTURED"),
+        }
+    }
+}
+
+/// Conversion method for SurfaceLights
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SurfaceLightMode {
+    /// Row of spot lights across the lit face
+    Lights,
+    /// Self-illuminated material on the lit part
+    SelfIllum,
+}
+
+impl Display for SurfaceLightMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SurfaceLightMode::Lights => write!(f, "LIGHTS"),
+            SurfaceLightMode::SelfIllum => write!(f, "SELFILLUM"),
+        }
+    }
+}
+
+/// Application of part colours to textures
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Shared texture per material, tinted with `$color`
+    Tinted,
+    /// Texture per material and colour, with the colour multiplied into the texture; Colours within `tolerance` (per channel) of an already baked colour reuse that texture
+    Baked { tolerance: u8 },
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMode::Tinted => write!(f, "TINTED"),
+            ColorMode::Baked { tolerance } => write!(f, "BAKED (tolerance {})", tolerance),
+        }
+    }
+}
+
+/// Target source engine game
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Game {
+    Css,
+    Csgo,
+    GMod,
+    Hl2,
+    Hl2Ep1,
+    Hl2Ep2,
+    Hl,
+    Hls,
+    L4d,
+    L4d2,
+    Portal2,
+    Portal,
+    Tf2,
+}
+
+impl Game {
+    pub fn from_id(id: &str) -> Option<Game> {
+        match id {
+            "css" => Some(Game::Css),
+            "csgo" => Some(Game::Csgo),
+            "gmod" => Some(Game::GMod),
+            "hl2" => Some(Game::Hl2),
+            "hl2e1" => Some(Game::Hl2Ep1),
+            "hl2e2" => Some(Game::Hl2Ep2),
+            "hl" => Some(Game::Hl),
+            "hls" => Some(Game::Hls),
+            "l4d" => Some(Game::L4d),
+            "l4d2" => Some(Game::L4d2),
+            "portal2" => Some(Game::Portal2),
+            "portal" => Some(Game::Portal),
+            "tf2" => Some(Game::Tf2),
+            _ => None
+        }
+    }
+
+    /// Entity class for climbable volumes, if supported by the game
+    pub fn ladder_class(self) -> Option<&'static str> {
+        match self {
+            Game::Tf2 => None,
+            Game::L4d | Game::L4d2 => None,     // Only supports func_simpleladder, which requires a climbing direction
+            _ => Some("func_ladder")
+        }
+    }
+
+    /// Entity class and keyvalues for a player spawn point
+    /// Teams are numbered in order of appearance, neutral spawns are alternated between teams in games that require a team
+    pub fn spawn_entity(self, team: Option<usize>, spawn_index: usize) -> (&'static str, Vec<(&'static str, String)>) {
+        match self {
+            Game::Tf2 => ("info_player_teamspawn", vec![("TeamNum", team.map(|team| 2 + team % 2).unwrap_or(0).to_string())]),    // 0 = Any, 2 = RED, 3 = BLU
+            Game::Css | Game::Csgo => match team.unwrap_or(spawn_index) % 2 {
+                0 => ("info_player_terrorist", Vec::new()),
+                _ => ("info_player_counterterrorist", Vec::new())
+            },
+            _ => ("info_player_start", Vec::new())
+        }
+    }
+}
+
 // This trait is "pub" as the wasm-specific code is in a separate crate, we do not have a public API
 pub trait ConvertOptions<W: Write> {
     fn print_output(&self) -> Box<dyn Write>;
     fn error_output(&self) -> Box<dyn Write>;
 
     fn input_name(&self) -> &str;
-    fn read_input_data<'a>(&'a self) -> OwnedOrRef<'a, String>;
+    fn read_input_data<'a>(&'a self) -> OwnedOrRef<'a, Vec<u8>>;
 
     fn vmf_output<'a>(&'a mut self) -> OwnedOrMut<'a, W>;
     fn texture_output<'a>(&'a mut self, path: &str) -> OwnedOrMut<'a, W>;
+    /// Reads a Roblox asset from local storage, if available
+    fn read_asset(&self, id: u64) -> Option<Vec<u8>>;
+    /// Returns true if local Roblox assets are available to [ConvertOptions::read_asset]
+    fn has_local_assets(&self) -> bool;
     fn texture_output_enabled(&self) -> bool;
     fn use_dev_textures(&self) -> bool;
+    fn color_mode(&self) -> ColorMode;
+    /// Replacement `$surfaceprop` values, by material texture name (E.g. "plastic", "rust")
+    fn surface_prop_overrides(&self) -> &[(String, String)];
 
     fn map_scale(&self) -> f64;
     fn auto_skybox_enabled(&self) -> bool;
     fn skybox_clearance(&self) -> f64;
     fn optimization_enabled(&self) -> bool;
+    fn cylinder_sides(&self) -> u32;
+    fn sphere_mode(&self) -> SphereMode;
+    fn truss_mode(&self) -> TrussMode;
+    fn surface_light_mode(&self) -> SurfaceLightMode;
+    /// Adds a knob brush to Hinge and Motor surfaces
+    fn hinge_knobs_enabled(&self) -> bool;
+    /// Paths of services and instances to read parts from, in addition to the Workspace (E.g. "ReplicatedStorage", "ServerStorage/Lobby")
+    fn included_paths(&self) -> &[String];
+    /// Reads parts of Tools and Accessories
+    fn include_tools(&self) -> bool;
+    /// Reads parts of character models
+    fn include_characters(&self) -> bool;
+    /// Glob patterns on the full path of instances to convert, along with their descendants (E.g. "Workspace/Map/**"); If any include filter is set, other instances are left out
+    fn included_path_patterns(&self) -> &[String];
+    /// Class names of instances to convert
+    fn included_classes(&self) -> &[String];
+    /// Glob patterns on the name of instances to convert
+    fn included_names(&self) -> &[String];
+    /// Glob patterns on the full path of instances to leave out, along with their descendants (E.g. "Workspace/Map/**")
+    fn excluded_paths(&self) -> &[String];
+    /// Class names of instances to leave out
+    fn excluded_classes(&self) -> &[String];
+    /// Glob patterns on the name of instances to leave out
+    fn excluded_names(&self) -> &[String];
 
     fn decal_size(&self) -> u64;
     fn skybox_name(&self) -> &str;
+    fn game(&self) -> Game;
 }
 
 /// Convert 3D Geometry with given options
@@ -80,239 +257,725 @@ pub async fn convert<W: Write, O: ConvertOptions<W>>(mut options: O) -> Result<u
     writeln!(print_out, "Converting {}", options.input_name())?;
     writeln!(print_out, "Texture output [{}]", if options.texture_output_enabled() { "ENABLED" } else { "DISABLED" })?;
     writeln!(print_out, "Texture mode [{}]", if options.use_dev_textures() { "DEVELOPER" } else { "NORMAL" })?;
+    writeln!(print_out, "Color mode [{}]", options.color_mode())?;
     writeln!(print_out, "Using map scale: {}×", options.map_scale())?;
     writeln!(print_out, "Auto-skybox [{}]", if options.auto_skybox_enabled() { "ENABLED" } else { "DISABLED" })?;
     writeln!(print_out, "Skybox clearance: +{}", options.skybox_clearance())?;
     writeln!(print_out, "Part-count optimization [{}]", if options.optimization_enabled() { "ENABLED" } else { "DISABLED" })?;
+    writeln!(print_out, "Cylinder sides: {}", options.cylinder_sides())?;
+    writeln!(print_out, "Sphere mode [{}]", options.sphere_mode())?;
+    writeln!(print_out, "Truss mode [{}]", options.truss_mode())?;
+    writeln!(print_out, "Surface light mode [{}]", options.surface_light_mode())?;
+    writeln!(print_out, "Hinge knobs [{}]", if options.hinge_knobs_enabled() { "ENABLED" } else { "DISABLED" })?;
     writeln!(print_out)?;
 
     write!(print_out, "Reading input...    ")?;    // We need to flush print! manually, as it is usually line-buffered.
     print_out.flush().unwrap_or_default();  // Error discarded; Failed flush causes no problems.
+    let input_data = options.read_input_data();
     writeln!(print_out, "DONE")?;
 
-    write!(print_out, "Parsing XML...      ")?;
-    print_out.flush().unwrap_or_default();
-    match Document::parse(options.read_input_data().as_ref()) {
-        Ok(document) => {
-            let mut parts = Vec::new();
-            parse::parse_xml(document.root_element(), &mut parts, false, options.decal_size());
-            writeln!(print_out, "{} parts found!", parts.len())?;
-
-            let mut bounding_box = parts.first()    // Initialise boundingbox based on first part, or default to origin coordinate
-                .copied()
-                .map(BoundingBox::from_part)
-                .unwrap_or(BoundingBox::zeros());
-
-            bounding_box = parts.iter()
-                .copied()
-                .fold(bounding_box, BoundingBox::include);
-
-            let map_size = if options.auto_skybox_enabled() {
-                (bounding_box.size() + Vector3::from_array([2.0, 2.0, 2.0])) * options.map_scale()    // Add auto-skybox size to map bounding box
-            } else {
-                bounding_box.size() * options.map_scale()
-            };
-            if (map_size.x >= MAX_MAP_SIZE) || (map_size.x >= MAX_MAP_SIZE) || (map_size.x >= MAX_MAP_SIZE) {
-                let rbx_map_size = if options.auto_skybox_enabled() {
-                    (MAX_MAP_SIZE / options.map_scale()) - 2.0
-                } else {
-                    MAX_MAP_SIZE / options.map_scale()
-                };
-
-                let largest_size = f64::max(map_size.x, map_size.y).max(map_size.z);
-                let max_scale_factor = options.map_scale() * (MAX_MAP_SIZE / largest_size);
-                
-                writeln!(error_out)?;
-                writeln!(error_out, "WARNING: Map exceeds source engine size limitations, and will not compile!")?;
-                writeln!(error_out, "Map size            X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", map_size.x, map_size.y, map_size.z)?;
-                writeln!(error_out, "Engine limits       X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", MAX_MAP_SIZE, MAX_MAP_SIZE, MAX_MAP_SIZE)?;
-                writeln!(error_out, "Map should be less than {} by {} by {} roblox units when using {}× scale", rbx_map_size, rbx_map_size, rbx_map_size, options.map_scale())?;
-                writeln!(error_out, "Map will fit with a scale factor of {:.2}×", max_scale_factor)?;
-                writeln!(error_out)?;
+    let tree = if binary::is_binary(input_data.as_ref()) {
+        write!(print_out, "Parsing binary...   ")?;
+        print_out.flush().unwrap_or_default();
+        match binary::parse_binary(input_data.as_ref()) {
+            Ok(tree) => tree,
+            Err(error) => {
+                writeln!(error_out, "error: invalid binary file {}", error)?;
                 error_out.flush()?;
-            } else {
-                writeln!(print_out, "Map size            X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", map_size.x, map_size.y, map_size.z)?;
+                return Ok(1);
             }
+        }
+    } else {
+        write!(print_out, "Parsing XML...      ")?;
+        print_out.flush().unwrap_or_default();
+        let document = match std::str::from_utf8(input_data.as_ref()).map(Document::parse) {
+            Ok(Ok(document)) => document,
+            Ok(Err(error)) => {
+                writeln!(error_out, "error: invalid XML {}", error)?;
+                error_out.flush()?;
+                return Ok(1);
+            }
+            Err(error) => {
+                writeln!(error_out, "error: invalid XML {}", error)?;
+                error_out.flush()?;
+                return Ok(1);
+            }
+        };
+        let mut tree = InstanceTree::new();
+        parse::parse_xml(document.root_element(), &mut tree, None);
+        tree
+    };
 
-            bounding_box.center_on_origin(&mut parts);
+    let material_variants = parse::parse_material_variants(&tree, options.decal_size());
+    if !material_variants.is_empty() {
+        writeln!(print_out, "{} material variants found!", material_variants.len())?;
+    }
 
-            if options.optimization_enabled() {
-                write!(print_out, "Optimizing...\n")?;
-                print_out.flush().unwrap_or_default();
-                let old_count = parts.len();
-                parts = Part::join_adjacent(parts, true, &mut print_out);
-                writeln!(print_out, "Reduced part count to {} (-{})", parts.len(), old_count - parts.len())?;
+    let mut included = Vec::new();
+    for path in options.included_paths() {
+        let instances = parse::find_instances(&tree, path);
+        if instances.is_empty() {
+            writeln!(error_out, "warning: included instance not found: {}", path)?;
+            error_out.flush()?;
+        }
+        included.extend(instances);
+    }
+    let roots = parse::traversal_roots(&tree, &included);
+
+    let mut filters = parse::InstanceFilters {
+        include: instance_filters(options.included_path_patterns(), options.included_classes(), options.included_names()),
+        exclude: instance_filters(options.excluded_paths(), options.excluded_classes(), options.excluded_names()),
+        include_removed: 0,
+    };
+    let filtered = filters.apply(&tree, &roots);
+
+    let part_settings = parse::PartSettings {
+        decal_size: options.decal_size(),
+        material_variants: &material_variants,
+        include_tools: options.include_tools(),
+        include_characters: options.include_characters(),
+        filtered: &filtered,
+    };
+    let mut parts = Vec::new();
+    let mut visgroups = Vec::new();
+    for root in &roots {
+        parse::parse_parts(&tree, *root, &mut parts, &mut visgroups, None, false, &part_settings);
+    }
+    writeln!(print_out, "{} parts found!", parts.len())?;
+    if !filters.include.is_empty() {
+        let include = filters.include.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        writeln!(print_out, "Include filters {} removed {} instances", include, filters.include_removed)?;
+    }
+    for filter in &filters.exclude {
+        writeln!(print_out, "Exclude filter {} removed {} instances", filter, filter.removed)?;
+    }
+
+    let mut lights = Vec::new();
+    for root in &roots {
+        parse::parse_lights(&tree, *root, &mut lights, &filtered);
+    }
+
+    let sky = parse::parse_sky(&tree);
+    let lighting = parse::parse_lighting(&tree);
+    if let Some(lighting) = lighting {
+        writeln!(print_out, "Lighting found, clock time: {:.2}", lighting.clock_time)?;
+    }
+
+    if options.sphere_mode() == SphereMode::Skip {
+        let old_count = parts.len();
+        parts.retain(|part| part.shape != PartShape::Sphere);
+        writeln!(print_out, "Skipped {} spheres", old_count - parts.len())?;
+    }
+
+    let mut bounding_box = parts.first()    // Initialise boundingbox based on first part, or default to origin coordinate
+        .copied()
+        .map(BoundingBox::from_part)
+        .unwrap_or(BoundingBox::zeros());
+
+    bounding_box = parts.iter()
+        .copied()
+        .fold(bounding_box, BoundingBox::include);
+
+    let map_size = if options.auto_skybox_enabled() {
+        (bounding_box.size() + Vector3::from_array([2.0, 2.0, 2.0])) * options.map_scale()    // Add auto-skybox size to map bounding box
+    } else {
+        bounding_box.size() * options.map_scale()
+    };
+    if (map_size.x >= MAX_MAP_SIZE) || (map_size.x >= MAX_MAP_SIZE) || (map_size.x >= MAX_MAP_SIZE) {
+        let rbx_map_size = if options.auto_skybox_enabled() {
+            (MAX_MAP_SIZE / options.map_scale()) - 2.0
+        } else {
+            MAX_MAP_SIZE / options.map_scale()
+        };
+
+        let largest_size = f64::max(map_size.x, map_size.y).max(map_size.z);
+        let max_scale_factor = options.map_scale() * (MAX_MAP_SIZE / largest_size);
+        
+        writeln!(error_out)?;
+        writeln!(error_out, "WARNING: Map exceeds source engine size limitations, and will not compile!")?;
+        writeln!(error_out, "Map size            X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", map_size.x, map_size.y, map_size.z)?;
+        writeln!(error_out, "Engine limits       X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", MAX_MAP_SIZE, MAX_MAP_SIZE, MAX_MAP_SIZE)?;
+        writeln!(error_out, "Map should be less than {} by {} by {} roblox units when using {}× scale", rbx_map_size, rbx_map_size, rbx_map_size, options.map_scale())?;
+        writeln!(error_out, "Map will fit with a scale factor of {:.2}×", max_scale_factor)?;
+        writeln!(error_out)?;
+        error_out.flush()?;
+    } else {
+        writeln!(print_out, "Map size            X: {:6.0}hu Y: {:6.0}hu Z: {:6.0}hu", map_size.x, map_size.y, map_size.z)?;
+    }
+
+    bounding_box.center_on_origin(&mut parts);
+
+    // Lights are matched to their parts before parts are split up or joined
+    let mut lit_parts = Vec::with_capacity(lights.len());
+    let mut light_count = 0;
+    for light in &lights {
+        if let Some(part) = parts.iter_mut().find(|part| part.referent == light.part) {
+            light_count += 1;
+            if options.surface_light_mode() == SurfaceLightMode::SelfIllum && matches!(light.light_type, LightType::Surface { .. }) && light.attachment.is_none() {
+                part.is_self_illuminated = true;
+            } else {
+                lit_parts.push((*light, *part));
             }
+        }
+    }
+    writeln!(print_out, "{} lights found!", light_count)?;
+    if light_count < lights.len() {
+        writeln!(print_out, "Skipped {} lights not attached to a converted part", lights.len() - light_count)?;
+    }
 
-            if parts.len() > MAX_BRUSH_COUNT {
-                writeln!(error_out, "error: Too many parts, found: {} parts, must be fewer than {}", parts.len(), MAX_BRUSH_COUNT + 1)?;
-                error_out.flush()?;
-                return Ok(1)
+    // Climbable volumes cover the whole truss, and are taken before trusses are split up
+    let ladders: Vec<Part> = if options.game().ladder_class().is_some() {
+        parts.iter()
+            .filter(|part| part.part_type == PartType::Truss)
+            .map(|part| ladder_volume(*part))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    match options.truss_mode() {
+        TrussMode::Lattice => {
+            parts = parts.into_iter()
+                .flat_map(|part| if part.part_type == PartType::Truss { truss_lattice(part) } else { vec![part] })
+                .collect();
+        }
+        TrussMode::Textured => {
+            parts.iter_mut()
+                .filter(|part| part.part_type == PartType::Truss)
+                .for_each(|part| {
+                    part.is_detail = true;
+                    part.material = TRUSS_MATERIAL;
+                });
+        }
+    }
+
+    if options.hinge_knobs_enabled() {
+        let knobs: Vec<Part> = parts.iter().flat_map(|part| hinge_knobs(*part)).collect();
+        writeln!(print_out, "Added {} hinge knobs", knobs.len())?;
+        parts.extend(knobs);
+    }
+
+    if let ColorMode::Baked { tolerance } = options.color_mode() {
+        // Snap part and decal colours onto a palette, so that near-identical colours share a baked texture
+        let mut palette: Vec<Color3> = Vec::new();
+        let mut snap = |color: &mut Color3| {
+            *color = match palette.iter().find(|baked| max_channel_difference(**baked, *color) <= tolerance) {
+                Some(baked) => *baked,
+                None => {
+                    palette.push(*color);
+                    *color
+                }
+            };
+        };
+        for part in &mut parts {
+            snap(&mut part.color);
+            for decal in part.overlays.iter_mut().flatten() {
+                snap(&mut decal.color);
             }
+        }
+        writeln!(print_out, "{} part and decal colors to bake", palette.len())?;
+    }
 
-            // Hack: Source engine does not support surface-displacement on detail
-            parts.iter_mut().for_each(|part| if part.shape != PartShape::Block { part.is_detail = false });
+    if options.optimization_enabled() {
+        write!(print_out, "Optimizing...\n")?;
+        print_out.flush().unwrap_or_default();
+        let old_count = parts.len();
+        parts = Part::join_adjacent(parts, true, &mut print_out);
+        writeln!(print_out, "Reduced part count to {} (-{})", parts.len(), old_count - parts.len())?;
+    }
 
-            let result: std::io::Result<()> = try {
-                let mut part_id = ID_BLOCK_SIZE * 0;    // IDs split into blocks to avoid overlap
-                let mut side_id = ID_BLOCK_SIZE * 1;
-                let mut entity_id = ID_BLOCK_SIZE * 2;
+    if parts.len() > MAX_BRUSH_COUNT {
+        writeln!(error_out, "error: Too many parts, found: {} parts, must be fewer than {}", parts.len(), MAX_BRUSH_COUNT + 1)?;
+        error_out.flush()?;
+        return Ok(1)
+    }
+
+    // Hack: Source engine does not support surface-displacement on detail
+    if let SphereMode::Displacement { .. } = options.sphere_mode() {
+        parts.iter_mut().for_each(|part| if part.shape == PartShape::Sphere { part.is_detail = false });
+    }
 
-                let mut texture_map = TextureMap::new();
+    let result: std::io::Result<()> = try {
+        let mut part_id = ID_BLOCK_SIZE * 0;    // IDs split into blocks to avoid overlap
+        let mut side_id = ID_BLOCK_SIZE * 1;
+        let mut entity_id = ID_BLOCK_SIZE * 2;
 
-                write!(print_out, "Writing VMF...      ")?;
-                print_out.flush().unwrap_or_default();
+        let mut texture_map = TextureMap::new();
 
-                let mut world_solids = Vec::with_capacity(parts.len());
-                let mut detail_solids = Vec::new();
+        write!(print_out, "Writing VMF...      ")?;
+        print_out.flush().unwrap_or_default();
+
+        let mut world_solids = Vec::with_capacity(parts.len());
+        let mut entities = Vec::new();
+        // Decals are placed on the sides of their part once it is decomposed
+        let mut overlays = Vec::new();
+
+        parts.iter()
+            .filter(|part| !part.is_detail)
+            .map(|part| {
+                let sides = decompose_part(*part, &mut side_id, options.map_scale(), options.use_dev_textures(), options.cylinder_sides(), options.sphere_mode(), &mut texture_map);
+                if !options.use_dev_textures() {
+                    overlays.extend(decal_overlays(*part, &sides, &mut entity_id, options.map_scale(), &mut texture_map));
+                }
+                Solid {
+                    id: {
+                        part_id += 1;
+                        part_id
+                    },
+                    sides,
+                    visgroups: part.visgroup.into_iter().collect(),
+                }
+            })
+            .for_each(|s| world_solids.push(s));
+
+        parts.iter()
+            .filter(|part| part.is_detail)
+            .map(|part| {
+                let sides = decompose_part(*part, &mut side_id, options.map_scale(), options.use_dev_textures(), options.cylinder_sides(), options.sphere_mode(), &mut texture_map);
+                if !options.use_dev_textures() {
+                    overlays.extend(decal_overlays(*part, &sides, &mut entity_id, options.map_scale(), &mut texture_map));
+                }
+                Entity {
+                    id: {
+                        entity_id += 1;
+                        entity_id
+                    },
+                    classname: "func_detail",
+                    keyvalues: Vec::new(),
+                    origin: None,
+                    solids: vec![Solid {
+                        id: {
+                            part_id += 1;
+                            part_id
+                        },
+                        sides,
+                        visgroups: part.visgroup.into_iter().collect(),
+                    }],
+                    connections: Vec::new(),
+                }
+            })
+            .for_each(|e| entities.push(e));
+        entities.extend(overlays);
 
-                parts.iter()
-                    .filter(|part| !part.is_detail)
-                    .map(|part| {
-                        Solid {
+        if let Some(ladder_class) = options.game().ladder_class() {
+            ladders.iter()
+                .map(|part| {
+                    Entity {
+                        id: {
+                            entity_id += 1;
+                            entity_id
+                        },
+                        classname: ladder_class,
+                        keyvalues: Vec::new(),
+                        origin: None,
+                        solids: vec![Solid {
                             id: {
                                 part_id += 1;
                                 part_id
                             },
-                            sides: decompose_part(*part, &mut side_id, options.map_scale(), options.use_dev_textures(), &mut texture_map),
-                        }
-                    })
-                    .for_each(|s| world_solids.push(s));
-
-                parts.iter()
-                    .filter(|part| part.is_detail)
-                    .map(|part| {
-                        (
-                            {
-                                entity_id += 1;
-                                entity_id
-                            },
-                            Solid {
-                                id: {
-                                    part_id += 1;
-                                    part_id
-                                },
-                                sides: decompose_part(*part, &mut side_id, options.map_scale(), options.use_dev_textures(), &mut texture_map),
-                            }
-                        )
+                            sides: decompose_part(*part, &mut side_id, options.map_scale(), false, options.cylinder_sides(), options.sphere_mode(), &mut texture_map),
+                            visgroups: part.visgroup.into_iter().collect(),
+                        }],
+                        connections: Vec::new(),
+                    }
+                })
+                .for_each(|e| entities.push(e));
+        }
+
+        // Team numbers are assigned to team colours in order of appearance
+        let mut team_colors = Vec::new();
+        parts.iter()
+            .filter_map(|part| match part.part_type {
+                PartType::SpawnLocation { team_color } => Some((*part, team_color)),
+                _ => None
+            })
+            .enumerate()
+            .map(|(spawn_index, (part, team_color))| {
+                let team = team_color.map(|team_color| {
+                    team_colors.iter().position(|color| *color == team_color).unwrap_or_else(|| {
+                        team_colors.push(team_color);
+                        team_colors.len() - 1
                     })
-                    .for_each(|s| detail_solids.push(s));
+                });
+                let (classname, keyvalues) = options.game().spawn_entity(team, spawn_index);
+                spawn_point(
+                    part,
+                    {
+                        entity_id += 1;
+                        entity_id
+                    },
+                    classname,
+                    keyvalues,
+                    options.map_scale(),
+                )
+            })
+            .for_each(|e| entities.push(e));
+
+        for (light, part) in lit_parts {
+            entities.extend(light_entities(light, part, &mut entity_id, options.map_scale()));
+        }
 
-                if options.auto_skybox_enabled() {
-                    bounding_box.y_max += options.skybox_clearance();
-                    world_solids.extend(generate_skybox(&mut part_id, &mut side_id, bounding_box, options.map_scale(), &mut texture_map));
+        if let Some(lighting) = lighting {
+            let skybox_clearance = if options.auto_skybox_enabled() { Some(options.skybox_clearance()) } else { None };
+            let origin = light_environment_origin(bounding_box, skybox_clearance, options.map_scale());
+            entities.push(light_environment(
+                lighting,
+                {
+                    entity_id += 1;
+                    entity_id
+                },
+                to_source_coordinates(origin * options.map_scale())
+            ));
+        }
+
+        if options.auto_skybox_enabled() {
+            bounding_box.y_max += options.skybox_clearance();
+            world_solids.extend(generate_skybox(&mut part_id, &mut side_id, bounding_box, options.map_scale(), &mut texture_map));
+        }
+
+        // Custom skies need their textures written out, and are only used if texture output is enabled
+        // Skies made of Roblox's built-in textures (rbxasset:// URLs), or without local assets to read, quietly use the default skybox
+        let is_custom_sky = |sky: &Sky| sky.faces.iter().any(|url| parse::asset_id(url).is_some());
+        let sky_textures = match sky {
+            Some(sky) if options.texture_output_enabled() && options.has_local_assets() && is_custom_sky(&sky) => match load_sky(sky, &options) {
+                Ok(sky_textures) => Some(sky_textures),
+                Err(error) => {
+                    writeln!(error_out, "warning: could not load sky, using default skybox: {}", error)?;
+                    error_out.flush()?;
+                    None
                 }
+            },
+            _ => None
+        };
+        let skyname = match &sky_textures {
+            Some((skyname, _)) => skyname.clone(),
+            None => options.skybox_name().to_string()  // Make owned copy; We want to borrow options mutable as well
+        };
 
-                let skyname = options.skybox_name().to_string();  // Make owned copy; We want to borrow options mutable as well
-
-                VMFBuilder(options.vmf_output().as_mut())
-                    .version_info(400, 3325, 0, false)? // Defaults from https://developer.valvesoftware.com/wiki/Valve_Map_Format
-                    .visgroups()?
-                    .viewsettings()?
-                    .world(0, &*skyname, world_solids, &texture_map)?
-                    .detail(detail_solids, &texture_map)?
-                    .flush()?;
-                writeln!(print_out, "DONE")?;
-
-                if options.texture_output_enabled() {
-                    write!(print_out, "Writing materials...\n")?;
-                    print_out.flush().unwrap_or_default();
-
-                    let mut textures_to_copy = Vec::new();  // We don't want to hash Material, and the low amount of entries in this Vec makes checking pretty fast.
-                    for texture in texture_map.into_iter().filter(RobloxTexture::must_generate) {
-                        if let Material::Decal { id, .. } | Material::Texture { id, .. } = texture.material {
-                            writeln!(print_out, "\tdecal: {} UNSUPPORTED", id)?;
-                        } else {
-                            write!(print_out, "\t{}...", texture.name())?;
-                            print_out.flush().unwrap_or_default();
-
-                            if !(textures_to_copy.contains(&texture.material)) {
-                                debug_assert!(!(matches!(texture.material, Material::Decal { .. }) && matches!(texture.material, Material::Texture { .. })));
-                                textures_to_copy.push(texture.material);
-                            }
+        VMFBuilder(options.vmf_output().as_mut())
+            .version_info(400, 3325, 0, false)? // Defaults from https://developer.valvesoftware.com/wiki/Valve_Map_Format
+            .visgroups(&visgroups)?
+            .viewsettings()?
+            .world(0, &*skyname, world_solids, &texture_map)?
+            .entities(entities, &texture_map)?
+            .flush()?;
+        writeln!(print_out, "DONE")?;
 
-                            let vmt_out_path = format!("{}.vmt", texture.name());
-                            let mut temp = options.texture_output(&*vmt_out_path);
-                            let file = temp.as_mut();
-                            let result: Result<(), io::Error> = try {
-                                write!(file,
-                                       "\"LightmappedGeneric\"\n\
-                                           {{\n\
-                                           \t$basetexture \"rbx/{}\"\n\
-                                           \t$color \"[{} {} {}]\"\n",
-                                       texture.material,
-                                       ((texture.color.red as f64) / 255.0).powf(2.2),  // Pow for gamma adjustment
-                                       ((texture.color.green as f64) / 255.0).powf(2.2),
-                                       ((texture.color.blue as f64) / 255.0).powf(2.2)
-                                )?;
-                                if texture.transparency != 255 {
-                                    write!(file, "\t$alpha {}\n", texture.transparency as f64 / 255.0)?;
-                                }
-                                if texture.reflectance != 0 {
-                                    write!(file, "\t$envmap env_cubemap\n")?;
-                                    write!(file, "\t$envmaptint \"[{reflectance} {reflectance} {reflectance}]\"\n", reflectance = 1.0 / (255.0 / (texture.reflectance as f64)))?;
-                                }
-                                write!(file, "}}\n")?;
-                            };
-                            if let Err(error) = result {
-                                writeln!(error_out, "\t\twarning: could not write VMT: {}", error)?;
-                                error_out.flush()?;
-                            } else {
-                                writeln!(print_out, " DONE")?;
-                            }
+        if options.texture_output_enabled() {
+            write!(print_out, "Writing materials...\n")?;
+            print_out.flush().unwrap_or_default();
+
+            let color_mode = options.color_mode();
+            let surface_prop_overrides = options.surface_prop_overrides().to_vec();
+            let mut textures_to_copy = Vec::new();  // We don't want to hash Material, and the low amount of entries in this Vec makes checking pretty fast.
+            let mut normal_maps = Vec::new();
+            for texture in texture_map.into_iter().filter(RobloxTexture::must_generate) {
+                write!(print_out, "\t{}...", texture.name())?;
+                print_out.flush().unwrap_or_default();
+
+                // Textures are never tinted, and white textures do not need a baked copy
+                let baked_color = match color_mode {
+                    ColorMode::Baked { .. } if (texture.material.texture().is_some() || matches!(texture.material, Material::Variant { .. } | Material::Decal { .. })) && texture.color != Color3::white() => Some(texture.color),
+                    _ => None
+                };
+                if !(textures_to_copy.contains(&(texture.material, baked_color))) {
+                    debug_assert!(!(matches!(texture.material, Material::Decal { .. }) && matches!(texture.material, Material::Texture { .. })));
+                    textures_to_copy.push((texture.material, baked_color));
+                }
+                let normal_map = match texture.material {
+                    Material::Variant { normal_map: Some(id), size_x, size_y, .. } => Some((id, size_x, size_y)),
+                    _ => None
+                };
+                if let Some(normal_map) = normal_map.filter(|normal_map| !normal_maps.contains(normal_map)) {
+                    normal_maps.push(normal_map);
+                }
+
+                let material_name = texture.material.to_string();
+                let surface_prop = surface_prop_overrides.iter()
+                    .find(|(material, _)| *material == material_name)
+                    .map(|(_, surface_prop)| surface_prop.clone())
+                    .or_else(|| texture.material.surface_prop().map(str::to_string));
+
+                let vmt_out_path = format!("{}.vmt", texture.name());
+                let mut temp = options.texture_output(&*vmt_out_path);
+                let file = temp.as_mut();
+                // ForceFields glow through an unlit additive shader, everything else is lit
+                let shader = match texture.material {
+                    Material::ForceField => "UnlitGeneric",
+                    _ => "LightmappedGeneric"
+                };
+                let result: Result<(), io::Error> = try {
+                    write!(file,
+                           "\"{}\"\n\
+                               {{\n\
+                               \t$basetexture \"rbx/{}\"\n",
+                           shader,
+                           baked_texture_name(texture.material, baked_color)
+                    )?;
+                    if let Some((id, ..)) = normal_map {
+                        writeln!(file, "\t$bumpmap \"rbx/normal_{}\"", id)?;
+                    }
+                    if let Some(surface_prop) = surface_prop {
+                        writeln!(file, "\t$surfaceprop \"{}\"", surface_prop)?;
+                    }
+                    if color_mode == ColorMode::Tinted {
+                        writeln!(file,
+                                 "\t$color \"[{} {} {}]\"",
+                                 ((texture.color.red as f64) / 255.0).powf(2.2),  // Pow for gamma adjustment
+                                 ((texture.color.green as f64) / 255.0).powf(2.2),
+                                 ((texture.color.blue as f64) / 255.0).powf(2.2)
+                        )?;
+                    }
+                    match texture.material {
+                        Material::ForceField => {
+                            writeln!(file, "\t$additive 1")?;
+                            writeln!(file, "\t$nocull 1")?;
+                        }
+                        Material::Custom { texture: "truss", .. } => writeln!(file, "\t$alphatest 1")?,    // Cut-out texture
+                        Material::Glass => writeln!(file, "\t$translucent 1")?,
+                        Material::Decal { .. } => writeln!(file, "\t$translucent 1")?,    // Overlay, transparent where the decal image is
+                        _ if texture.transparency != 255 => writeln!(file, "\t$translucent 1")?,
+                        _ => {}
+                    }
+                    if (texture.self_illuminated || texture.material == Material::Neon) && shader == "LightmappedGeneric" {
+                        writeln!(file, "\t$selfillum 1")?;
+                    }
+                    if texture.transparency != 255 {
+                        writeln!(file, "\t$alpha {}", texture.transparency as f64 / 255.0)?;
+                    }
+                    // Glass always reflects its surroundings, even without Reflectance
+                    let reflectance = match texture.material {
+                        Material::Glass => (texture.reflectance as f64 / 255.0).max(GLASS_REFLECTANCE),
+                        _ => texture.reflectance as f64 / 255.0
+                    };
+                    if reflectance != 0.0 {
+                        writeln!(file, "\t$envmap env_cubemap")?;
+                        writeln!(file, "\t$envmaptint \"[{reflectance} {reflectance} {reflectance}]\"", reflectance = reflectance)?;
+                    }
+                    if texture.material == Material::ForceField {
+                        writeln!(file,
+                                 "\t\"Proxies\"\n\
+                                 \t{{\n\
+                                 \t\t\"TextureScroll\"\n\
+                                 \t\t{{\n\
+                                 \t\t\t\"texturescrollvar\" \"$basetexturetransform\"\n\
+                                 \t\t\t\"texturescrollrate\" {}\n\
+                                 \t\t\t\"texturescrollangle\" 45\n\
+                                 \t\t}}\n\
+                                 \t}}",
+                                 FORCEFIELD_SCROLL_RATE
+                        )?;
+                    }
+                    writeln!(file, "}}")?;
+                };
+                if let Err(error) = result {
+                    writeln!(error_out, "\t\twarning: could not write VMT: {}", error)?;
+                    error_out.flush()?;
+                } else {
+                    writeln!(print_out, " DONE")?;
+                }
+            }
+
+            if let Some((skyname, faces)) = sky_textures {
+                writeln!(print_out, "Writing skybox {}...", skyname)?;
+                for (suffix, face) in SKY_FACES {
+                    let image = &faces[face];
+                    let result = {
+                        let vtf_out_path = format!("skybox/{}{}.vtf", skyname, suffix);
+                        let mut temp = options.texture_output(&vtf_out_path);
+                        vtf::write_vtf(temp.as_mut(), image.width() as u16, image.height() as u16, image.as_raw(), vtf::ImageFormat::Bgr888, vtf::FLAG_CLAMP_S | vtf::FLAG_CLAMP_T | vtf::FLAG_NO_MIP | vtf::FLAG_NO_LOD)
+                    };
+                    let result = result.and_then(|_| {
+                        let vmt_out_path = format!("skybox/{}{}.vmt", skyname, suffix);
+                        let mut temp = options.texture_output(&vmt_out_path);
+                        write!(
+                            temp.as_mut(),
+                            "\"UnlitGeneric\"\n\
+                            {{\n\
+                            \t$basetexture \"skybox/{}{}\"\n\
+                            \t$nofog 1\n\
+                            \t$ignorez 1\n\
+                            }}\n",
+                            skyname, suffix
+                        )
+                    });
+                    if let Err(error) = result {
+                        writeln!(error_out, "\t\twarning: could not write skybox face {}: {}", suffix, error)?;
+                        error_out.flush()?;
+                    }
+                }
+            }
+
+            write!(print_out, "Copying textures...\n")?;
+            print_out.flush().unwrap_or_default();
+            for (material, baked_color) in textures_to_copy {
+                let texture_name = baked_texture_name(material, baked_color);
+                write!(print_out, "\t{}...", texture_name)?;
+                print_out.flush().unwrap_or_default();
+
+                // Decals, Textures and MaterialVariants are read from local assets, with a bundled texture as placeholder
+                let (asset, bundled) = match material {
+                    Material::Decal { id, size_x, size_y } | Material::Texture { id, size_x, size_y, .. } => (Some((id, size_x, size_y)), Some(crate::rbx::textures::DECAL)),
+                    Material::Variant { base_material, color_map, size_x, size_y, .. } => (Some((color_map, size_x, size_y)), Material::from_id(base_material).and_then(Material::texture)),
+                    _ => (None, material.texture())
+                };
+                let image = match asset {
+                    Some((id, size_x, size_y)) => load_asset_image(&options, id)
+                        .map(|image| Some(image::imageops::resize(&image, size_x as u32, size_y as u32, FilterType::Triangle))),
+                    None => Ok(None)
+                };
+                let status = match (&image, baked_color) {
+                    (Ok(Some(_)), _) => "WRITTEN".to_string(),
+                    (Err(error), _) => format!("PLACEHOLDER ({})", error),
+                    (Ok(None), Some(_)) => "BAKED".to_string(),
+                    (Ok(None), None) => "COPIED".to_string(),
+                };
+                let data = match (image, bundled) {
+                    (Ok(Some(image)), _) => {
+                        let (width, height) = (image.width() as u16, image.height() as u16);
+                        let rgba = match baked_color {
+                            Some(color) => bake_color(image.into_raw(), color),
+                            None => image.into_raw()
                         };
+                        Some(vtf::encode_vtf(width, height, &rgba, vtf::ImageFormat::compressed_for(&rgba), 0))
                     }
+                    (_, Some(bundled)) => match baked_color {
+                        Some(color) => bake_vtf(bundled, color),
+                        None => Some(bundled.to_vec())
+                    },
+                    (_, None) => {
+                        writeln!(print_out, " SKIPPED")?;
+                        continue;
+                    }
+                };
+                let Some(data) = data else {
+                    writeln!(error_out, "\t\t could not decode texture {}", material)?;
+                    error_out.flush()?;
+                    continue;
+                };
 
-                    write!(print_out, "Copying textures...\n")?;
-                    print_out.flush().unwrap_or_default();
-                    for material in textures_to_copy {
-                        write!(print_out, "\t{}...", material)?;
-                        print_out.flush().unwrap_or_default();
-
-                        if let Some(data) = material.texture() {
-                            let texture_path = format!("rbx/{}.vtf", material);
-                            let mut temp = options.texture_output(&*texture_path);
-                            let file = temp.as_mut();
-
-                            match file.write_all(&*data) {
-                                Ok(()) => writeln!(print_out, " COPIED")?,
-                                Err(error) => {
-                                    writeln!(error_out, "\t\t could not copy texture file {}: {}", material, error)?;
-                                    error_out.flush()?;
-                                }
-                            }
-                        } else {
-                            writeln!(print_out, " SKIPPED")?;
-                        }
+                let texture_path = format!("rbx/{}.vtf", texture_name);
+                let mut temp = options.texture_output(&texture_path);
+                match temp.as_mut().write_all(&data) {
+                    Ok(()) => writeln!(print_out, " {}", status)?,
+                    Err(error) => {
+                        writeln!(error_out, "\t\t could not write texture file {}: {}", texture_name, error)?;
+                        error_out.flush()?;
+                    }
+                }
+            }
+
+            for (id, size_x, size_y) in normal_maps {
+                write!(print_out, "\tnormal_{}...", id)?;
+                print_out.flush().unwrap_or_default();
+
+                // Normal maps that can not be found locally are replaced with a flat normal map
+                let (width, height, rgba, status) = match load_asset_image(&options, id) {
+                    Ok(image) => {
+                        let image = image::imageops::resize(&image, size_x as u32, size_y as u32, FilterType::Triangle);
+                        (image.width() as u16, image.height() as u16, image.into_raw(), "WRITTEN".to_string())
+                    }
+                    Err(error) => (4, 4, [128, 128, 255, 255].repeat(16), format!("PLACEHOLDER ({})", error))
+                };
+                let texture_path = format!("rbx/normal_{}.vtf", id);
+                let mut temp = options.texture_output(&texture_path);
+                match vtf::write_vtf(temp.as_mut(), width, height, &rgba, vtf::ImageFormat::Bgr888, vtf::FLAG_NORMAL) {
+                    Ok(()) => writeln!(print_out, " {}", status)?,
+                    Err(error) => {
+                        writeln!(error_out, "\t\t could not write texture file normal_{}: {}", id, error)?;
+                        error_out.flush()?;
                     }
                 }
-            };
-            if let Err(error) = result {
-                writeln!(error_out, "error: could not write VMF {}", error)?;
-                error_out.flush()?;
-                return Ok(1);
             }
-            Ok(0)
         }
-        Err(error) => {
-            writeln!(error_out, "error: invalid XML {}", error)?;
-            error_out.flush()?;
-            return Ok(1);
+    };
+    if let Err(error) = result {
+        writeln!(error_out, "error: could not write VMF {}", error)?;
+        error_out.flush()?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+/// Source engine skybox face suffixes, with the index of the Roblox sky face (in Bk, Dn, Ft, Lf, Rt, Up order) shown on them
+/// Roblox's front face is at -Z, which becomes the back (+Y) face in source engine coordinates.
+const SKY_FACES: [(&str, usize); 6] = [("bk", 2), ("dn", 1), ("ft", 0), ("lf", 3), ("rt", 4), ("up", 5)];
+/// Smallest environment map tint of glass materials
+const GLASS_REFLECTANCE: f64 = 0.25;
+/// Scrolling speed of ForceField textures, in texture widths per second
+const FORCEFIELD_SCROLL_RATE: f64 = 0.1;
+
+/// Largest size of skybox faces, in pixels
+const MAX_SKY_SIZE: u32 = 1024;
+
+/// Largest difference between the channels of two colours
+fn max_channel_difference(a: Color3, b: Color3) -> u8 {
+    a.red.abs_diff(b.red).max(a.green.abs_diff(b.green)).max(a.blue.abs_diff(b.blue))
+}
+
+/// Name of the VTF texture for a material, with a suffix for baked colours
+fn baked_texture_name(material: Material, baked_color: Option<Color3>) -> String {
+    match baked_color {
+        Some(color) => format!("{}_{:x}-{:x}-{:x}", material, color.red, color.green, color.blue),
+        None => material.to_string()
+    }
+}
+
+/// Multiplies a colour into an RGBA image
+fn bake_color(mut rgba: Vec<u8>, color: Color3) -> Vec<u8> {
+    for pixel in rgba.chunks_exact_mut(4) {
+        for (channel, tint) in pixel.iter_mut().zip([color.red, color.green, color.blue]) {
+            *channel = ((*channel as u32 * tint as u32 + 127) / 255) as u8;
         }
     }
+    rgba
+}
+
+/// Multiplies a colour into a bundled VTF texture
+fn bake_vtf(vtf: &[u8], color: Color3) -> Option<Vec<u8>> {
+    let (width, height, flags, rgba) = vtf::decode_vtf(vtf)?;
+    let rgba = bake_color(rgba, color);
+    Some(vtf::encode_vtf(width, height, &rgba, vtf::ImageFormat::compressed_for(&rgba), flags))
+}
+
+/// Loads and decodes an image asset from local storage
+fn load_asset_image<W: Write, O: ConvertOptions<W>>(options: &O, id: u64) -> Result<RgbaImage, String> {
+    let data = options.read_asset(id).ok_or_else(|| format!("asset {} not found", id))?;
+    Ok(image::load_from_memory(&data).map_err(|error| format!("asset {}: {}", id, error))?.to_rgba8())
+}
+
+/// Loads the faces of a sky from local assets, resized to a power of two, and returns them with the sky's name
+fn load_sky<W: Write, O: ConvertOptions<W>>(sky: Sky, options: &O) -> Result<(String, [RgbaImage; 6]), String> {
+    let mut ids = [0; 6];
+    for (id, url) in ids.iter_mut().zip(sky.faces) {
+        *id = parse::asset_id(url).ok_or_else(|| format!("invalid content URL \"{}\"", url))?;
+    }
+
+    let mut faces = Vec::with_capacity(6);
+    for id in ids {
+        let image = load_asset_image(options, id)?;
+        let size = 1 << image.width().max(image.height()).clamp(1, MAX_SKY_SIZE).ilog2();    // Largest power of two that fits
+        faces.push(if image.width() == size && image.height() == size {
+            image
+        } else {
+            image::imageops::resize(&image, size, size, FilterType::Triangle)
+        });
+    }
+    // Named after the upwards face, as most skies have a unique top
+    Ok((format!("rbx_sky_{}", ids[5]), faces.try_into().unwrap()))
 }
 
 /// Converts roblox coordinates to source engine coordinates
 fn to_source_coordinates(vector: Vector3) -> [f64; 3] {
     [
         vector.x,
-        -vector.z, // Negation corrects for mirroring in hammer/VMF
+        -vector.z + 0.0, // Negation corrects for mirroring in hammer/VMF, adding zero keeps zeroes positive
         vector.y
     ]
 }
 
 /// Decomposes a Roblox part into it's polyhedron faces, and returns them as source engine Sides
-fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bool, texture_map: &mut TextureMap<RobloxTexture>) -> Vec<Side> {
+fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bool, cylinder_sides: u32, sphere_mode: SphereMode, texture_map: &mut TextureMap<RobloxTexture>) -> Vec<Side> {
     // Source engine does not support extremely thin brushes, deleting the small faces leading to geometry errors.
     // Parts are given a minimum size as a workaround.
 
@@ -333,18 +996,144 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
     const DECAL_LEFT: usize = 3;
 
     // First three boundaries of a plane form the defining points, in the order required by source engine
-    let planes = [
-        ([vertices[5], vertices[7], vertices[4], vertices[6]], DECAL_TOP),      // +Y
-        ([vertices[0], vertices[2], vertices[1], vertices[3]], DECAL_BOTTOM),   // -Y
-        ([vertices[2], vertices[7], vertices[6], vertices[3]], DECAL_RIGHT),    // -X
-        ([vertices[5], vertices[0], vertices[1], vertices[4]], DECAL_LEFT),     // +X
-        ([vertices[3], vertices[4], vertices[7], vertices[0]], DECAL_FRONT),    // -Z
-        ([vertices[6], vertices[1], vertices[2], vertices[5]], DECAL_BACK)      // +Z
-    ];
+    // Faces that are not aligned to the part's axes specify their texture axes, other faces take the texture axes of the closest cuboid face
+    // Curved faces take the decal of the cuboid face their part-space normal is closest to
+    let closest_decal_side = |normal: Vector3| match normal.closest_axis().array() {
+        [x, _, _] if x > 0.0 => DECAL_RIGHT,
+        [x, _, _] if x < 0.0 => DECAL_LEFT,
+        [_, y, _] if y > 0.0 => DECAL_TOP,
+        [_, y, _] if y < 0.0 => DECAL_BOTTOM,
+        [_, _, z] if z > 0.0 => DECAL_BACK,
+        _ => DECAL_FRONT,
+    };
+    // Texture axes are given in part-space, with the V axis running down the face
+    let aligned_texture_face = |u_axis: Vector3, v_axis: Vector3| Some((u_axis, v_axis));
+    // Cuboid faces are upright towards the top face, the top and bottom faces run down towards +X
+    let cuboid_texture_axes = |normal: Vector3| {
+        let normal = normal.closest_axis();
+        if normal.y != 0.0 {
+            (Vector3 { x: -1.0, y: 0.0, z: 0.0 }.cross(normal), Vector3 { x: 1.0, y: 0.0, z: 0.0 })
+        } else {
+            (Vector3 { x: 0.0, y: 1.0, z: 0.0 }.cross(normal), Vector3 { x: 0.0, y: -1.0, z: 0.0 })
+        }
+    };
+    let planes = match part.part_type {
+        PartType::Wedge => {
+            // The slope replaces the top and front faces, running from the bottom front edge to the top back edge.
+            // Planes extend infinitely, so the side faces keep their cuboid planes and get clipped into triangles by the slope.
+            let slope_decal = if part.decals[DECAL_TOP].is_some() { DECAL_TOP } else { DECAL_FRONT };
+            vec![
+                (
+                    [vertices[3], vertices[5], vertices[6], vertices[0]],
+                    slope_decal,
+                    aligned_texture_face(Vector3 { x: -1.0, y: 0.0, z: 0.0 }, Vector3 { x: 0.0, y: -part.size.y, z: -part.size.z }),
+                    0
+                ),   // Slope
+                ([vertices[0], vertices[2], vertices[1], vertices[3]], DECAL_BOTTOM, None, 0),   // -Y
+                ([vertices[2], vertices[7], vertices[6], vertices[3]], DECAL_LEFT, None, 0),     // -X
+                ([vertices[5], vertices[0], vertices[1], vertices[4]], DECAL_RIGHT, None, 0),    // +X
+                ([vertices[6], vertices[1], vertices[2], vertices[5]], DECAL_BACK, None, 0)      // +Z
+            ]
+        }
+        PartType::CornerWedge => {
+            // Pyramid with its peak above the +X -Z corner; Two slopes replace the +Y, +Z and -X faces.
+            // The slope planes are defined using the cuboid vertices that lie on them, so that texture fill spans the full slope.
+            vec![
+                ([vertices[0], vertices[2], vertices[1], vertices[3]], DECAL_BOTTOM, None, 0),   // -Y
+                ([vertices[5], vertices[0], vertices[1], vertices[4]], DECAL_RIGHT, None, 0),    // +X
+                ([vertices[3], vertices[4], vertices[7], vertices[0]], DECAL_FRONT, None, 0),    // -Z
+                (
+                    [vertices[1], vertices[7], vertices[4], vertices[2]],
+                    DECAL_BACK,
+                    aligned_texture_face(Vector3 { x: 1.0, y: 0.0, z: 0.0 }, Vector3 { x: 0.0, y: -part.size.y, z: part.size.z }),
+                    0
+                ),   // +Y +Z slope
+                (
+                    [vertices[2], vertices[4], vertices[5], vertices[3]],
+                    DECAL_LEFT,
+                    aligned_texture_face(Vector3 { x: 0.0, y: 0.0, z: 1.0 }, Vector3 { x: -part.size.x, y: -part.size.y, z: 0.0 }),
+                    0
+                ),   // -X +Y slope
+            ]
+        }
+        _ if part.shape == PartShape::Cylinder => {
+            // Prism along the X axis, inscribed in the cylinder; Roblox uses the smaller of the Y and Z sizes as diameter
+            // Faces are centered on the Y and Z axes, so that prisms with a multiple of 4 sides have flat top, bottom, front and back faces.
+            let radius = part.size.y.min(part.size.z) / 2.0;
+            let half_length = part.size.x / 2.0;
+            let rim_point = |x: f64, angle: f64| Vector3 { x, y: radius * angle.cos(), z: radius * angle.sin() } * part.cframe;
+
+            let mut planes = vec![
+                ([vertices[2], vertices[7], vertices[6], vertices[3]], DECAL_LEFT, None, 0),     // -X
+                ([vertices[5], vertices[0], vertices[1], vertices[4]], DECAL_RIGHT, None, 0),    // +X
+            ];
+            let side_count = cylinder_sides.clamp(3, MAX_CYLINDER_SIDES);
+            for side in 0..side_count {
+                let angle = std::f64::consts::TAU * (side as f64) / (side_count as f64);
+                let half_width = std::f64::consts::PI / (side_count as f64);
+                let decal_side = closest_decal_side(Vector3 { x: 0.0, y: angle.cos(), z: angle.sin() });
+                planes.push((
+                    [
+                        rim_point(-half_length, angle - half_width),
+                        rim_point(half_length, angle - half_width),
+                        rim_point(half_length, angle + half_width),
+                        rim_point(-half_length, angle + half_width)
+                    ],
+                    decal_side,
+                    None,
+                    1
+                ));
+            }
+            planes
+        }
+        _ if part.shape == PartShape::Sphere && matches!(sphere_mode, SphereMode::Polyhedron { .. }) => {
+            // Inscribed polyhedron made of latitude rings around the Y axis, with flat caps at the poles, stretched to fit the part's size
+            // Source engine brushes can have at most 128 sides, limiting the number of segments.
+            let SphereMode::Polyhedron { segments } = sphere_mode else { unreachable!() };
+            let segments = segments.clamp(MIN_SPHERE_SEGMENTS, MAX_SPHERE_SEGMENTS);
+            let rings = segments / 2;
+            let half_size = part.size / 2.0;
+            let ring_point = |ring: u32, segment: u32| {
+                let latitude = std::f64::consts::PI * ((ring + 1) as f64) / ((rings + 1) as f64) - std::f64::consts::FRAC_PI_2;
+                let longitude = std::f64::consts::TAU * (segment as f64) / (segments as f64);
+                Vector3 { x: latitude.cos() * longitude.cos(), y: latitude.sin(), z: latitude.cos() * longitude.sin() } * half_size * part.cframe
+            };
+            // Reverse the points of faces whose winding points inward
+            let outward = |face: [Vector3; 4]| {
+                if (face[0] - face[1]).cross(face[2] - face[1]).dot(Vector3::centroid(face) - part.cframe.position) < 0.0 {
+                    [face[3], face[2], face[1], face[0]]
+                } else {
+                    face
+                }
+            };
+
+            let mut faces = vec![
+                outward([ring_point(0, 0), ring_point(0, segments / 4), ring_point(0, segments / 2), ring_point(0, 3 * segments / 4)]),
+                outward([ring_point(rings - 1, 0), ring_point(rings - 1, segments / 4), ring_point(rings - 1, segments / 2), ring_point(rings - 1, 3 * segments / 4)]),
+            ];
+            for ring in 0..(rings - 1) {
+                for segment in 0..segments {
+                    let next_segment = (segment + 1) % segments;
+                    faces.push(outward([ring_point(ring, segment), ring_point(ring, next_segment), ring_point(ring + 1, next_segment), ring_point(ring + 1, segment)]));
+                }
+            }
+            faces.into_iter()
+                .map(|face| (face, closest_decal_side((Vector3::centroid(face) / part.cframe) / half_size), None, 1))
+                .collect()
+        }
+        _ => vec![
+            ([vertices[5], vertices[7], vertices[4], vertices[6]], DECAL_TOP, None, 0),      // +Y
+            ([vertices[0], vertices[2], vertices[1], vertices[3]], DECAL_BOTTOM, None, 0),   // -Y
+            ([vertices[2], vertices[7], vertices[6], vertices[3]], DECAL_LEFT, None, 0),     // -X
+            ([vertices[5], vertices[0], vertices[1], vertices[4]], DECAL_RIGHT, None, 0),    // +X
+            ([vertices[3], vertices[4], vertices[7], vertices[0]], DECAL_FRONT, None, 0),    // -Z
+            ([vertices[6], vertices[1], vertices[2], vertices[5]], DECAL_BACK, None, 0)      // +Z
+        ]
+    };
 
     let part_centroid = part.cframe.position;
 
-    let sides = planes.into_iter().map(|(plane, decal_side)| {
+    let sides = planes.into_iter().map(|(plane, decal_side, texture_axes, smoothing_groups)| {
         // Calculate normal vectors of the plane
         let vector_a = plane[0] - plane[1];
         let vector_b = plane[2] - plane[1];
@@ -373,28 +1162,6 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
             normal_a
         };
 
-        // Determine which cardinal direction the plane normal vector points; This will be the direction from which the texture is rendered in source engine.
-        let texture_face = if out_vector.x.abs() >= out_vector.y.abs() && out_vector.x.abs() >= out_vector.z.abs() {
-            if out_vector.x.is_sign_positive() {
-                TextureFace::X_POS
-            } else {
-                TextureFace::X_NEG
-            }
-        } else if out_vector.y.abs() >= out_vector.x.abs() && out_vector.y.abs() >= out_vector.z.abs() {
-            if out_vector.y.is_sign_positive() {
-                TextureFace::Y_POS
-            } else {
-                TextureFace::Y_NEG
-            }
-        } else {
-            debug_assert!(out_vector.z.abs() >= out_vector.x.abs() && out_vector.z.abs() >= out_vector.y.abs());
-            if out_vector.z.is_sign_positive() {
-                TextureFace::Z_POS
-            } else {
-                TextureFace::Z_NEG
-            }
-        };
-
         let texture =
             if use_dev_textures {
                 match part.material {
@@ -412,6 +1179,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -430,6 +1198,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -448,6 +1217,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -466,6 +1236,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -484,6 +1255,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -502,6 +1274,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
@@ -520,13 +1293,14 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                             reflectance: 0,
                             scale: TextureScale::FIXED { scale_x: 0.25, scale_z: 0.25 },
                             no_offset: true,
+                            self_illuminated: false,
                             dimension_x: 64,
                             dimension_y: 64
                         }
                     }
                 }
             } else if let Some(side_decal) = part.decals[decal_side] {
-                let (color, transparency) = if let Material::Custom { texture: "decal", .. } = &side_decal {    // Slight hack: Do not color "decal" textures
+                let (color, transparency) = if let Material::Custom { texture: "decal", .. } | Material::Texture { .. } = &side_decal {    // Slight hack: Do not color decal textures
                     (Color3::white(), 255)
                 } else {
                     (part.color, (255.0 * (1.0 - part.transparency)) as u8)
@@ -537,7 +1311,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                     transparency,
                     reflectance: (255.0 * part.reflectance) as u8,
                     scale: match side_decal {
-                        Material::Decal { .. } | Material::Custom { fill: true, .. } => TextureScale::FILL,
+                        Material::Custom { fill: true, .. } => TextureScale::FILL,
                         Material::Texture { size_x, size_y, studs_per_u, studs_per_v, .. } => {
                             TextureScale::FIXED {
                                 scale_x: map_scale * studs_per_u / (size_x as f64),
@@ -547,6 +1321,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                         _ => TextureScale::FIXED { scale_x: map_scale / 32.0, scale_z: map_scale / 32.0 },
                     },
                     no_offset: false,
+                    self_illuminated: false,
                     dimension_x: side_decal.dimension_x(),
                     dimension_y: side_decal.dimension_y(),
                 }
@@ -556,157 +1331,60 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                     color: part.color,
                     transparency: (255.0 * (1.0 - part.transparency)) as u8,
                     reflectance: (255.0 * part.reflectance) as u8,
-                    scale: TextureScale::FIXED { scale_x: map_scale / 32.0, scale_z: map_scale / 32.0 },
+                    scale: match part.material {
+                        Material::Variant { size_x, size_y, studs_per_tile, .. } => TextureScale::FIXED {
+                            scale_x: map_scale * studs_per_tile / (size_x as f64),
+                            scale_z: map_scale * studs_per_tile / (size_y as f64),
+                        },
+                        _ => TextureScale::FIXED { scale_x: map_scale / 32.0, scale_z: map_scale / 32.0 },
+                    },
                     no_offset: false,
+                    self_illuminated: part.is_self_illuminated,
                     dimension_x: part.material.dimension_x(),
                     dimension_y: part.material.dimension_y(),
                 }
             };
 
-        let displacement = match part.shape {
-            PartShape::Sphere => {
-                let (mut offsets, offset_normals) = match texture_face {
-                    TextureFace::X_POS => {
-                        let offsets = [
-                            [-237.861, 237.861, 237.861, -201.755, 92.3116, 201.755, -183.246, 0.0, 183.246, -201.755, -92.3116, 201.755, -237.861, -237.861, 237.861],
-                            [-201.755, 201.755, 92.3116, -125.21, 84.672, 84.6719, -100.481, 0.0, 77.4723, -125.21, -84.6719, 84.6719, -201.755, -201.755, 92.3116],
-                            [-183.246, 183.246, 0.0, -100.481, 77.4723, 0.0, -69.1808, 0.0, 0.0, -100.481, -77.4723, 0.0, -183.246, -183.246, 0.0],
-                            [-201.755, 201.755, -92.3116, -125.21, 84.672, -84.672, -100.481, 0.0, -77.4723, -125.21, -84.672, -84.672, -201.755, -201.755, -92.3116],
-                            [-237.861, 237.861, -237.861, -201.755, 92.3116, -201.755, -183.246, 0.0, -183.246, -201.755, -92.3116, -201.755, -237.861, -237.861, -237.861]
-                        ];
-                        let offset_normals = [
-                            [0.563819, -0.563819, -0.563819, 0.653738, -0.305397, -0.658052, 0.690534, 0.0, -0.690534, 0.655635, 0.303805, -0.656901, 0.563819, 0.563819, -0.563819],
-                            [0.656901, -0.655635, -0.303805, 0.890386, -0.296795, -0.269814, 0.917459, -0.00062678, -0.334578, 0.883334, 0.294445, -0.294445, 0.649801, 0.662669, -0.303812],
-                            [0.690534, -0.690534, 0.0, 0.918221, -0.332479, -0.00122529, 0.976562, 0.0, 0.0, 0.917459, 0.334578, 0.00062678, 0.690534, 0.690534, 0.0],
-                            [0.658053, -0.653738, 0.305397, 0.901673, -0.265198, 0.265198, 0.918221, 0.00122529, 0.332479, 0.890386, 0.269814, 0.296795, 0.65954, 0.652252, 0.305366],
-                            [0.563819, -0.563819, 0.563819, 0.662669, -0.303812, 0.649801, 0.690534, 0.0, 0.690534, 0.652252, 0.305367, 0.65954, 0.563819, 0.563819, 0.563819]
-                        ];
-                        (offsets, offset_normals)
-                    }
-                    TextureFace::X_NEG => {
-                        let offsets = [
-                            [237.861, 237.861, 237.861, 201.755, 201.755, 92.3116, 183.246, 183.246, 0.0, 201.755, 201.755, -92.3116, 237.861, 237.861, -237.861],
-                            [201.755, 92.3116, 201.755, 125.211, 84.672, 84.6719, 100.481, 77.4723, 0.0, 125.211, 84.672, -84.672, 201.755, 92.3116, -201.755],
-                            [183.246, 0.0, 183.246, 100.481, 0.0, 77.4723, 69.181, 0.0, 0.0, 100.481, 0.0, -77.4723, 183.246, 0.0, -183.246],
-                            [201.755, -92.3116, 201.755, 125.211, -84.672, 84.6719, 100.481, -77.4723, 0.0, 125.211, -84.672, -84.672, 201.755, -92.3116, -201.755],
-                            [237.861, -237.861, 237.861, 201.755, -201.755, 92.3116, 183.246, -183.246, 0.0, 201.755, -201.755, -92.3116, 237.861, -237.861, -237.861]
-                        ];
-                        let offset_normals = [
-                            [-0.563819, -0.563819, -0.563819, -0.65954, -0.652252, -0.305366, -0.690534, -0.690534, 0.0, -0.649801, -0.662669, 0.303812, -0.563819, -0.563819, 0.563819],
-                            [-0.652252, -0.305367, -0.65954, -0.890386, -0.269814, -0.296795, -0.917459, -0.334578, -0.00062678, -0.883334, -0.294445, 0.294445, -0.655635, -0.303805, 0.656901],
-                            [-0.690534, 0.0, -0.690534, -0.918221, -0.0012253, -0.332479, -0.976562, 0.0, 0.0, -0.917459, 0.000626779, 0.334578, -0.690534, 0.0, 0.690534],
-                            [-0.662669, 0.303812, -0.649801, -0.901673, 0.265198, -0.265198, -0.918221, 0.332479, 0.00122528, -0.890386, 0.296795, 0.269814, -0.653738, 0.305397, 0.658053],
-                            [-0.563819, 0.563819, -0.563819, -0.658053, 0.653738, -0.305397, -0.690534, 0.690534, 0.0, -0.656901, 0.655635, 0.303805, -0.563819, 0.563819, 0.563819]
-                        ];
-                        (offsets, offset_normals)
-                    }
-                    TextureFace::Z_POS => {
-                        let offsets = [
-                            [237.861, 237.861, 237.861, 92.3116, 201.755, 201.755, 0.0, 183.246, 183.246, -92.3116, 201.755, 201.755, -237.861, 237.861, 237.861],
-                            [201.755, 201.755, 92.3116, 84.6719, 125.21, 84.6719, 0.0, 100.481, 77.4723, -84.672, 125.21, 84.6719, -201.755, 201.755, 92.3116],
-                            [183.246, 183.246, 0.0, 77.4723, 100.481, 0.0, 0.0, 69.1808, 0.0, -77.4723, 100.481, 0.0, -183.246, 183.246, 0.0],
-                            [201.755, 201.755, -92.3116, 84.672, 125.21, -84.672, 0.0, 100.481, -77.4723, -84.672, 125.21, -84.672, -201.755, 201.755, -92.3116],
-                            [237.861, 237.861, -237.861, 92.3116, 201.755, -201.755, 0.0, 183.246, -183.246, -92.3116, 201.755, -201.755, -237.861, 237.861, -237.861]
-                        ];
-                        let offset_normals = [
-                            [-0.563819, -0.563819, -0.563819, -0.305367, -0.65954, -0.652252, 0.0, -0.690534, -0.690534, 0.303813, -0.649801, -0.662669, 0.563819, -0.563819, -0.563819],
-                            [-0.65954, -0.652252, -0.305366, -0.296795, -0.890386, -0.269814, -0.00062678, -0.917459, -0.334578, 0.294445, -0.883334, -0.294445, 0.656901, -0.655635, -0.303805],
-                            [-0.690534, -0.690534, 0.0, -0.332479, -0.918221, -0.0012253, 0.0, -0.976562, 0.0, 0.334578, -0.917459, 0.00062678, 0.690534, -0.690534, 0.0],
-                            [-0.649801, -0.662669, 0.303812, -0.265198, -0.901673, 0.265198, 0.00122531, -0.918221, 0.332479, 0.269814, -0.890386, 0.296795, 0.658053, -0.653738, 0.305397],
-                            [-0.563819, -0.563819, 0.563819, -0.305397, -0.658052, 0.653738, 0.0, -0.690534, 0.690534, 0.303805, -0.656901, 0.655635, 0.563819, -0.563819, 0.563819]
-                        ];
-                        (offsets, offset_normals)
-                    }
-                    TextureFace::Z_NEG => {
-                        let offsets = [
-                            [237.861, -237.861, 237.861, 201.755, -201.755, 92.3116, 183.246, -183.246, 0.0, 201.755, -201.755, -92.3116, 237.861, -237.861, -237.861],
-                            [92.3116, -201.755, 201.755, 84.672, -125.211, 84.6719, 77.4723, -100.481, 0.0, 84.6719, -125.211, -84.672, 92.3116, -201.755, -201.755],
-                            [0.0, -183.246, 183.246, 0.0, -100.481, 77.4723, 0.0, -69.181, 0.0, 0.0, -100.481, -77.4723, 0.0, -183.246, -183.246],
-                            [-92.3116, -201.755, 201.755, -84.672, -125.211, 84.6719, -77.4723, -100.481, 0.0, -84.672, -125.211, -84.672, -92.3116, -201.755, -201.755],
-                            [-237.861, -237.861, 237.861, -201.755, -201.755, 92.3116, -183.246, -183.246, 0.0, -201.755, -201.755, -92.3116, -237.861, -237.861, -237.861]
-                        ];
-                        let offset_normals = [
-                            [-0.563819, 0.563819, -0.563819, -0.658053, 0.653738, -0.305397, -0.690534, 0.690534, 0.0, -0.656901, 0.655635, 0.303805, -0.563819, 0.563819, 0.563819],
-                            [-0.303805, 0.656901, -0.655635, -0.269814, 0.890386, -0.296795, -0.334578, 0.917459, -0.00062678, -0.294445, 0.883334, 0.294445, -0.303812, 0.649801, 0.662669],
-                            [0.0, 0.690534, -0.690534, -0.00122528, 0.918221, -0.332479, 0.0, 0.976562, 0.0, 0.00062678, 0.917459, 0.334578, 0.0, 0.690534, 0.690534],
-                            [0.305397, 0.658053, -0.653738, 0.265198, 0.901673, -0.265198, 0.332479, 0.918221, 0.00122528, 0.296795, 0.890386, 0.269814, 0.305366, 0.65954, 0.652252],
-                            [0.563819, 0.563819, -0.563819, 0.649801, 0.662669, -0.303812, 0.690534, 0.690534, 0.0, 0.65954, 0.652252, 0.305366, 0.563819, 0.563819, 0.563819]
-                        ];
-                        (offsets, offset_normals)
-                    }
-                    TextureFace::Y_NEG => {
-                        let offsets = [
-                            [237.861, 237.861, 237.861, 201.755, 92.3116, 201.755, 183.246, 0.0, 183.246, 201.755, -92.3116, 201.755, 237.861, -237.861, 237.861],
-                            [92.3116, 201.755, 201.755, 84.672, 84.672, 125.21, 77.4723, 0.0, 100.481, 84.6719, -84.6719, 125.21, 92.3116, -201.755, 201.755],
-                            [0.0, 183.246, 183.246, 0.0, 77.4723, 100.481, 0.0, 0.0, 69.1808, 0.0, -77.4723, 100.481, 0.0, -183.246, 183.246],
-                            [-92.3116, 201.755, 201.755, -84.672, 84.672, 125.21, -77.4723, 0.0, 100.481, -84.672, -84.672, 125.21, -92.3116, -201.755, 201.755],
-                            [-237.861, 237.861, 237.861, -201.755, 92.3116, 201.755, -183.246, 0.0, 183.246, -201.755, -92.3116, 201.755, -237.861, -237.861, 237.861],
-                        ];
-                        let offset_normals = [
-                            [-0.563819, -0.563819, -0.563819, -0.652252, -0.305367, -0.65954, -0.690534, 0.0, -0.690534, -0.662669, 0.303812, -0.649801, -0.563819, 0.563819, -0.563819],
-                            [-0.305367, -0.65954, -0.652252, -0.269814, -0.296795, -0.890386, -0.334578, -0.00062678, -0.917459, -0.294445, 0.294445, -0.883334, -0.303805, 0.656901, -0.655635],
-                            [0.0, -0.690534, -0.690534, -0.00122527, -0.332479, -0.918221, 0.0, 0.0, -0.976562, 0.00062678, 0.334578, -0.917459, 0.0, 0.690534, -0.690534],
-                            [0.303813, -0.649801, -0.662669, 0.265198, -0.265198, -0.901673, 0.332479, 0.00122528, -0.918221, 0.296795, 0.269814, -0.890386, 0.305397, 0.658053, -0.653738],
-                            [0.563819, -0.563819, -0.563819, 0.653738, -0.305397, -0.658052, 0.690534, 0.0, -0.690534, 0.655635, 0.303805, -0.656901, 0.563819, 0.563819, -0.563819],
-                        ];
-                        (offsets, offset_normals)
-                    }
-                    TextureFace::Y_POS => {
-                        let offsets = [
-                            [237.861, 237.861, -237.861, 92.3116, 201.755, -201.755, 0.0, 183.246, -183.246, -92.3116, 201.755, -201.755, -237.861, 237.861, -237.861],
-                            [201.755, 92.3116, -201.755, 84.6719, 84.672, -125.211, 0.0, 77.4723, -100.481, -84.672, 84.672, -125.21, -201.755, 92.3116, -201.755],
-                            [183.246, 0.0, -183.246, 77.4723, 0.0, -100.481, 0.0, 0.0, -69.1809, -77.4723, 0.0, -100.481, -183.246, 0.0, -183.246],
-                            [201.755, -92.3116, -201.755, 84.672, -84.672, -125.211, 0.0, -77.4723, -100.481, -84.672, -84.672, -125.21, -201.755, -92.3116, -201.755],
-                            [237.861, -237.861, -237.861, 92.3116, -201.755, -201.755, 0.0, -183.246, -183.246, -92.3116, -201.755, -201.755, -237.861, -237.861, -237.861],
-                        ];
-                        let offset_normals = [
-                            [-0.563819, -0.563819, 0.563819, -0.305397, -0.658052, 0.653738, 0.0, -0.690534, 0.690534, 0.303805, -0.656901, 0.655635, 0.563819, -0.563819, 0.563819],
-                            [-0.655635, -0.303805, 0.656901, -0.296795, -0.269814, 0.890386, -0.00062678, -0.334578, 0.917459, 0.294445, -0.294445, 0.883334, 0.662669, -0.303812, 0.649801],
-                            [-0.690534, 0.0, 0.690534, -0.332479, -0.00122531, 0.918221, 0.0, 0.0, 0.976562, 0.334578, 0.00062678, 0.917459, 0.690534, 0.0, 0.690534],
-                            [-0.653738, 0.305397, 0.658053, -0.265198, 0.265198, 0.901673, 0.00122531, 0.332479, 0.918221, 0.269814, 0.296795, 0.890386, 0.652252, 0.305367, 0.65954],
-                            [-0.563819, 0.563819, 0.563819, -0.303812, 0.649801, 0.662669, 0.0, 0.690534, 0.690534, 0.305366, 0.65954, 0.652252, 0.563819, 0.563819, 0.563819],
-                        ];
-                        (offsets, offset_normals)
-                    }
-                };
-                let [size_x, size_y, size_z] = part.size.array();
-                for row in &mut offsets {
-                    row[0] *= size_x * map_scale / 1000.0;
-                    row[1] *= size_y * map_scale / 1000.0;
-                    row[2] *= size_z * map_scale / 1000.0;
-                    row[3] *= size_x * map_scale / 1000.0;
-                    row[4] *= size_y * map_scale / 1000.0;
-                    row[5] *= size_z * map_scale / 1000.0;
-                    row[6] *= size_x * map_scale / 1000.0;
-                    row[7] *= size_y * map_scale / 1000.0;
-                    row[8] *= size_z * map_scale / 1000.0;
-                    row[9] *= size_x * map_scale / 1000.0;
-                    row[10] *= size_y * map_scale / 1000.0;
-                    row[11] *= size_z * map_scale / 1000.0;
-                    row[12] *= size_x * map_scale / 1000.0;
-                    row[13] *= size_y * map_scale / 1000.0;
-                    row[14] *= size_z * map_scale / 1000.0;
-                }
-
-                Some(Displacement {
-                    offsets,
-                    offset_normals,
-                    start_position: to_source_coordinates({
-                        let mut x = f64::MAX;
-                        let mut y = f64::MAX;
-                        let mut z = f64::MIN;
-
-                        for vector in plane {
-                            x = x.min(vector.x);
-                            y = y.min(vector.y);
-                            z = z.max(vector.z);
-                        }
-                        Vector3 { x, y, z } * map_scale
-                    }),
-                })
+        // Textures follow the rotation of their part, anchored to the top left corner of the face for Textures, or the third plane point for other materials
+        // Developer textures are aligned to the world grid instead; The world axis the plane normal vector points closest to is the direction from which the texture is rendered in source engine.
+        let texture_face = if texture_axes.is_some() || !use_dev_textures {
+            let (u_axis, v_axis) = texture_axes.unwrap_or_else(|| cuboid_texture_axes(part.cframe.vector_to_object_space(out_vector)));
+            let (u_axis, v_axis) = (part.cframe.vector_to_world_space(u_axis.unit()), part.cframe.vector_to_world_space(v_axis.unit()));
+            let origin = match texture.material {
+                Material::Texture { .. } => plane.into_iter()
+                    .min_by(|a, b| a.dot(u_axis + v_axis).total_cmp(&b.dot(u_axis + v_axis)))
+                    .unwrap_or(plane[2]),
+                _ => plane[2]
+            };
+            TextureFace::ALIGNED {
+                u_axis: to_source_coordinates(u_axis),
+                v_axis: to_source_coordinates(v_axis),
+                origin: to_source_coordinates(origin * map_scale),
             }
-            PartShape::Cylinder => None,
-            PartShape::Block => None,
+        } else if out_vector.x.abs() >= out_vector.y.abs() && out_vector.x.abs() >= out_vector.z.abs() {
+            if out_vector.x.is_sign_positive() {
+                TextureFace::X_POS
+            } else {
+                TextureFace::X_NEG
+            }
+        } else if out_vector.y.abs() >= out_vector.x.abs() && out_vector.y.abs() >= out_vector.z.abs() {
+            if out_vector.y.is_sign_positive() {
+                TextureFace::Y_POS
+            } else {
+                TextureFace::Y_NEG
+            }
+        } else {
+            debug_assert!(out_vector.z.abs() >= out_vector.x.abs() && out_vector.z.abs() >= out_vector.y.abs());
+            if out_vector.z.is_sign_positive() {
+                TextureFace::Z_POS
+            } else {
+                TextureFace::Z_NEG
+            }
+        };
+
+        let displacement = match (part.shape, sphere_mode) {
+            (PartShape::Sphere, SphereMode::Displacement { power }) => Some(sphere_displacement(part, plane, power, map_scale)),
+            _ => None,
         };
 
         let side = Side {
@@ -718,6 +1396,7 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
                 to_source_coordinates(plane[1] * map_scale),
                 to_source_coordinates(plane[2] * map_scale)
             ],
+            smoothing_groups,
             displacement,
         };
         *id += 1;
@@ -727,6 +1406,447 @@ fn decompose_part(part: Part, id: &mut u32, map_scale: f64, use_dev_textures: bo
     sides
 }
 
+/// Filters on instance paths, class names and names, in that order
+fn instance_filters(paths: &[String], classes: &[String], names: &[String]) -> Vec<parse::InstanceFilter> {
+    paths.iter().map(|pattern| parse::InstanceFilter::new(parse::FilterKind::Path, pattern))
+        .chain(classes.iter().map(|class| parse::InstanceFilter::new(parse::FilterKind::Class, class)))
+        .chain(names.iter().map(|pattern| parse::InstanceFilter::new(parse::FilterKind::Name, pattern)))
+        .collect()
+}
+
+/// Converts the decals of a part into info_overlay entities on its sides, drawn over the part's own material
+/// Decals cover every side facing within 60 degrees of their face, so that they wrap around curved parts.
+fn decal_overlays(part: Part, sides: &[Side], entity_id: &mut u32, map_scale: f64, texture_map: &mut TextureMap<RobloxTexture>) -> Vec<Entity<'static>> {
+    let mut overlays = Vec::new();
+    for (face, decal) in part.overlays.into_iter().enumerate() {
+        let Some(decal) = decal else { continue };
+
+        // Face normals in NormalId order: Right, Top, Back, Left, Bottom, Front
+        // Decals stand upright towards the top face like textures do, with the top and bottom faces running down towards +X.
+        let axis = face % 3;
+        let sign = if face < 3 { 1.0 } else { -1.0 };
+        let mut normal = [0.0; 3];
+        normal[axis] = sign;
+        let (normal, up, center, half_height) = if part.part_type == PartType::Wedge && (face == 1 || face == 5) {
+            // Top and front decals of wedges are placed on the slope, which runs through the part's center
+            let slope = Vector3 { x: 0.0, y: part.size.y, z: part.size.z };
+            (Vector3 { x: 0.0, y: part.size.z, z: -part.size.y }.unit(), slope.unit(), Vector3 { x: 0.0, y: 0.0, z: 0.0 }, slope.magnitude() / 2.0)
+        } else {
+            let normal = Vector3::from_array(normal);
+            let up = if axis == 1 { Vector3 { x: -1.0, y: 0.0, z: 0.0 } } else { Vector3 { x: 0.0, y: 1.0, z: 0.0 } };
+            (normal, up, normal * part.size / 2.0, (up * part.size).magnitude() / 2.0)
+        };
+        let right = up.cross(normal);
+        let half_width = (right * part.size).magnitude() / 2.0;
+
+        let world_normal = to_source_coordinates(part.cframe.vector_to_world_space(normal));
+        let side_ids = sides.iter()
+            .filter(|side| {
+                let [a, b, c] = side.plane.map(Vector3::from_array);
+                (a - b).cross(c - b).unit().dot(Vector3::from_array(world_normal)) > 0.5
+            })
+            .map(|side| side.id.to_string())
+            .collect::<Vec<_>>();
+        if side_ids.is_empty() {
+            continue;
+        }
+
+        let texture = RobloxTexture {
+            material: decal.material,
+            color: decal.color,
+            transparency: (255.0 * (1.0 - decal.transparency)) as u8,
+            reflectance: 0,
+            scale: TextureScale::FILL,
+            no_offset: true,
+            self_illuminated: false,
+            dimension_x: decal.material.dimension_x(),
+            dimension_y: decal.material.dimension_y(),
+        };
+        let material = texture.name();
+        texture_map.store(texture);
+
+        let direction = |vector: Vector3| {
+            let [x, y, z] = to_source_coordinates(part.cframe.vector_to_world_space(vector));
+            format!("{} {} {}", x, y, z)
+        };
+        let origin = to_source_coordinates((center * part.cframe) * map_scale);
+        let (half_width, half_height) = (half_width * map_scale, half_height * map_scale);
+        overlays.push(Entity {
+            id: {
+                *entity_id += 1;
+                *entity_id
+            },
+            classname: "info_overlay",
+            keyvalues: vec![
+                ("material", material),
+                ("sides", side_ids.join(" ")),
+                ("RenderOrder", "0".to_string()),
+                // Texture V runs from the top of the image downwards, against BasisV
+                ("StartU", "0".to_string()),
+                ("EndU", "1".to_string()),
+                ("StartV", "1".to_string()),
+                ("EndV", "0".to_string()),
+                ("BasisOrigin", format!("{} {} {}", origin[0], origin[1], origin[2])),
+                ("BasisU", direction(right)),
+                ("BasisV", direction(up)),
+                ("BasisNormal", direction(normal)),
+                ("uv0", format!("{} {} 0", -half_width, -half_height)),
+                ("uv1", format!("{} {} 0", -half_width, half_height)),
+                ("uv2", format!("{} {} 0", half_width, half_height)),
+                ("uv3", format!("{} {} 0", half_width, -half_height)),
+            ],
+            origin: Some(origin),
+            solids: Vec::new(),
+            connections: Vec::new(),
+        });
+    }
+    overlays
+}
+
+const TRUSS_MATERIAL: Material = Material::Custom { texture: "truss", fill: false, generate: true, size_x: 64, size_y: 64 };
+
+/// Splits a truss into detail parts for its rails and rungs, with rungs on each side every 2 studs
+/// Diagonal supports are not included.
+fn truss_lattice(truss: Part) -> Vec<Part> {
+    const RAIL_SIZE: f64 = 0.3;
+
+    // Trusses are 2 by 2 studs, extending along a single axis
+    let size = truss.size.array();
+    let length_axis = (0..3).max_by(|left, right| size[*left].total_cmp(&size[*right])).unwrap();
+    let (axis_a, axis_b) = ((length_axis + 1) % 3, (length_axis + 2) % 3);
+    let segments = (size[length_axis] / 2.0).round().max(1.0) as usize;
+
+    let piece = |center: [f64; 3], piece_size: [f64; 3]| Part {
+        part_type: PartType::Part,
+        shape: PartShape::Block,
+        is_detail: true,
+        size: Vector3::from_array(piece_size),
+        cframe: CFrame { position: Vector3::from_array(center) * truss.cframe, rot_matrix: truss.cframe.rot_matrix },
+        decals: [None; 6],
+        overlays: [None; 6],
+        ..truss
+    };
+    // Center and size of a piece, with its coordinate along the length axis and the two other axes
+    let place = |length: f64, a: f64, b: f64| {
+        let mut vector = [0.0; 3];
+        vector[length_axis] = length;
+        vector[axis_a] = a;
+        vector[axis_b] = b;
+        vector
+    };
+
+    let rail_a = size[axis_a] / 2.0 - RAIL_SIZE / 2.0;
+    let rail_b = size[axis_b] / 2.0 - RAIL_SIZE / 2.0;
+    let mut pieces = Vec::with_capacity(4 + 4 * (segments + 1));
+    for (sign_a, sign_b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        pieces.push(piece(place(0.0, sign_a * rail_a, sign_b * rail_b), place(size[length_axis], RAIL_SIZE, RAIL_SIZE)));
+    }
+    for rung in 0..=segments {
+        let length = -size[length_axis] / 2.0 + RAIL_SIZE / 2.0 + (rung as f64) * (size[length_axis] - RAIL_SIZE) / (segments as f64);
+        for sign in [-1.0, 1.0] {
+            pieces.push(piece(place(length, sign * rail_a, 0.0), place(RAIL_SIZE, RAIL_SIZE, size[axis_b] - 2.0 * RAIL_SIZE)));
+            pieces.push(piece(place(length, 0.0, sign * rail_b), place(RAIL_SIZE, size[axis_a] - 2.0 * RAIL_SIZE, RAIL_SIZE)));
+        }
+    }
+    pieces
+}
+
+/// Returns the climbable volume of a truss; Slightly larger than the truss, so players pressed against it are inside the volume
+fn ladder_volume(truss: Part) -> Part {
+    let size = truss.size.array();
+    let length_axis = (0..3).max_by(|left, right| size[*left].total_cmp(&size[*right])).unwrap();
+    let mut volume_size = size.map(|size| size + 1.0);
+    volume_size[length_axis] = size[length_axis];
+    Part {
+        part_type: PartType::Part,
+        shape: PartShape::Block,
+        is_detail: false,
+        size: Vector3::from_array(volume_size),
+        material: Material::Custom { texture: "tools/toolsinvisibleladder", fill: false, generate: false, size_x: 128, size_y: 128 },
+        decals: [None; 6],
+        overlays: [None; 6],
+        ..truss
+    }
+}
+
+/// Length and diameter of the knobs of Hinge and Motor surfaces, in studs
+const HINGE_KNOB_LENGTH: f64 = 0.5;
+const HINGE_KNOB_DIAMETER: f64 = 0.6;
+/// Colour of hinge knobs, Roblox's "Bright yellow"
+const HINGE_KNOB_COLOR: Color3 = Color3 { red: 245, green: 205, blue: 48 };
+
+/// Returns cylinder detail parts for the knobs sticking out of the middle of a part's Hinge and Motor surfaces
+/// Knobs are only placed on parts whose faces are flat, or round around the knob.
+fn hinge_knobs(part: Part) -> Vec<Part> {
+    if part.part_type != PartType::Part || part.shape == PartShape::Sphere {
+        return Vec::new();
+    }
+    let mut knobs = Vec::new();
+    for (face, decal) in part.decals.into_iter().enumerate() {
+        let Some(Material::Custom { texture: "hinge" | "motor" | "steppingmotor", .. }) = decal else { continue };
+
+        // Face normals in NormalId order: Right, Top, Back, Left, Bottom, Front
+        let axis = face % 3;
+        let sign = if face < 3 { 1.0 } else { -1.0 };
+        let mut normal = [0.0; 3];
+        normal[axis] = sign;
+        let mut other = [0.0; 3];
+        other[(axis + 1) % 3] = 1.0;
+        // Cylinders extend along their X axis, which points out of the face
+        let normal = part.cframe.vector_to_world_space(Vector3::from_array(normal));
+        let other = part.cframe.vector_to_world_space(Vector3::from_array(other));
+        let mut center = [0.0; 3];
+        center[axis] = sign * (part.size.array()[axis] + HINGE_KNOB_LENGTH) / 2.0;
+        knobs.push(Part {
+            part_type: PartType::Part,
+            shape: PartShape::Cylinder,
+            is_detail: true,
+            is_self_illuminated: false,
+            size: Vector3 { x: HINGE_KNOB_LENGTH, y: HINGE_KNOB_DIAMETER, z: HINGE_KNOB_DIAMETER },
+            cframe: CFrame {
+                position: Vector3::from_array(center) * part.cframe,
+                rot_matrix: [normal.array(), other.array(), normal.cross(other).array()],
+            },
+            color: HINGE_KNOB_COLOR,
+            transparency: 0.0,
+            reflectance: 0.0,
+            material: Material::SmoothPlastic,
+            decals: [None; 6],
+            overlays: [None; 6],
+            ..part
+        });
+    }
+    knobs
+}
+
+/// Multiplier from Roblox light brightness to source engine light intensity
+const LIGHT_INTENSITY: f64 = 200.0;
+/// Distance between lights placed across a SurfaceLight's face, in studs
+const SURFACE_LIGHT_SPACING: f64 = 4.0;
+/// Maximum number of lights placed along each axis of a SurfaceLight's face
+const SURFACE_LIGHT_MAX_ROW: usize = 8;
+
+/// Converts a light into light entities
+/// Point lights are placed at the center of their part, which is usually non-solid or translucent.
+/// Spot lights and surface lights are placed just in front of their face, as source engine lights inside of brushes do not emit any light.
+/// Lights parented to an Attachment are placed at the Attachment, shining along its orientation.
+fn light_entities(light: Light, part: Part, entity_id: &mut u32, map_scale: f64) -> Vec<Entity<'static>> {
+    let offset = 1.0 / map_scale;   // 1 hammer unit
+    let mut intensity = light.brightness * LIGHT_INTENSITY;
+    let mut keyvalues = vec![
+        ("_lightHDR", "-1 -1 -1 1".to_string()),
+        ("_fifty_percent_distance", (light.range * map_scale / 2.0).to_string()),
+        ("_zero_percent_distance", (light.range * map_scale).to_string()),
+    ];
+
+    let cframe = match light.attachment {
+        Some(attachment) => CFrame {
+            position: attachment.position * part.cframe,
+            rot_matrix: attachment.rot_matrix.map(|column| part.cframe.vector_to_world_space(Vector3::from_array(column)).array()),
+        },
+        None => part.cframe
+    };
+
+    let mut positions = Vec::new();
+    let (classname, direction) = match light.light_type {
+        LightType::Point => {
+            positions.push(cframe.position);
+            ("light", None)
+        }
+        LightType::Spot { angle, face } | LightType::Surface { angle, face } => {
+            // Face normals in NormalId order: Right, Top, Back, Left, Bottom, Front
+            let axis = face % 3;
+            let sign = if face < 3 { 1.0 } else { -1.0 };
+            let size = part.size.array();
+            let mut normal = [0.0; 3];
+            normal[axis] = sign;
+            let direction = cframe.vector_to_world_space(Vector3::from_array(normal));
+            let mut center = [0.0; 3];
+            center[axis] = sign * size[axis] / 2.0;
+
+            if light.attachment.is_some() {
+                positions.push(cframe.position);
+            } else if let LightType::Surface { .. } = light.light_type {
+                // Lights are spread over a grid across the face
+                let (axis_a, axis_b) = ((axis + 1) % 3, (axis + 2) % 3);
+                let count_a = ((size[axis_a] / SURFACE_LIGHT_SPACING).ceil() as usize).clamp(1, SURFACE_LIGHT_MAX_ROW);
+                let count_b = ((size[axis_b] / SURFACE_LIGHT_SPACING).ceil() as usize).clamp(1, SURFACE_LIGHT_MAX_ROW);
+                intensity /= (count_a * count_b) as f64;    // Total output does not depend on the size of the part
+                for index_a in 0..count_a {
+                    for index_b in 0..count_b {
+                        let mut position = center;
+                        position[axis_a] = size[axis_a] * ((index_a as f64 + 0.5) / count_a as f64 - 0.5);
+                        position[axis_b] = size[axis_b] * ((index_b as f64 + 0.5) / count_b as f64 - 0.5);
+                        positions.push(Vector3::from_array(position) * part.cframe + direction * offset);
+                    }
+                }
+            } else {
+                positions.push(Vector3::from_array(center) * part.cframe + direction * offset);
+            }
+
+            let cone = (angle / 2.0).clamp(1.0, 90.0);
+            keyvalues.push(("_cone", cone.to_string()));
+            keyvalues.push(("_inner_cone", (cone / 2.0).to_string()));
+            ("light_spot", Some(direction))
+        }
+    };
+
+    keyvalues.insert(0, ("_light", format!("{} {} {} {}", light.color.red, light.color.green, light.color.blue, intensity)));
+    if let Some(direction) = direction {
+        keyvalues.extend(light_angles(direction));
+    }
+
+    positions.into_iter()
+        .map(|position| Entity {
+            id: {
+                *entity_id += 1;
+                *entity_id
+            },
+            classname,
+            keyvalues: keyvalues.clone(),
+            origin: Some(to_source_coordinates(position * map_scale)),
+            solids: Vec::new(),
+            connections: Vec::new(),
+        })
+        .collect()
+}
+
+/// Direction keyvalues of a light shining in the given direction
+/// The "pitch" keyvalue used by VRAD is positive upwards, while the "angles" shown by hammer are positive downwards.
+fn light_angles(direction: Vector3) -> [(&'static str, String); 2] {
+    let direction = to_source_coordinates(direction);
+    let pitch = direction[2].clamp(-1.0, 1.0).asin().to_degrees();
+    let yaw = direction[1].atan2(direction[0]).to_degrees();
+    [
+        ("pitch", pitch.to_string()),
+        ("angles", format!("{} {} 0", -pitch, yaw)),
+    ]
+}
+
+/// Intensity of the ambient light of a light_environment, for full-white ambient colours
+const AMBIENT_INTENSITY: f64 = 100.0;
+
+/// Position of the light_environment, above the center of the map inside the auto-skybox, otherwise just below the top of the map so that it does not cause a leak
+/// The position is kept at least 1 hammer unit inside the map, as the skybox clearance may be smaller than that.
+fn light_environment_origin(bounding_box: BoundingBox, skybox_clearance: Option<f64>, map_scale: f64) -> Vector3 {
+    let hammer_unit = 1.0 / map_scale;
+    let y = match skybox_clearance {
+        Some(clearance) => (bounding_box.y_max + hammer_unit).min(bounding_box.y_max + clearance - hammer_unit),
+        None => bounding_box.y_max - hammer_unit
+    };
+    Vector3 { y: y.max(bounding_box.y_min + hammer_unit), ..bounding_box.center() }
+}
+
+/// Converts lighting settings into a light_environment
+/// The sun is placed as on an equinox, in a world where -Z is north; At night the moon shines from the opposite direction, at reduced brightness.
+fn light_environment(lighting: Lighting, id: u32, origin: [f64; 3]) -> Entity<'static> {
+    let hour_angle = ((lighting.clock_time - 12.0) * 15.0).to_radians();
+    let latitude = lighting.geographic_latitude.to_radians();
+    let sun = Vector3 {
+        x: -hour_angle.sin(),
+        y: latitude.cos() * hour_angle.cos(),
+        z: latitude.sin() * hour_angle.cos(),
+    };
+    let (light_source, brightness) = if sun.y >= 0.0 {
+        (sun, lighting.brightness)
+    } else {
+        (Vector3 { x: -sun.x, y: -sun.y, z: -sun.z }, lighting.brightness * 0.1)
+    };
+
+
+    // Source only has a single ambient colour, Roblox lights outdoor areas with both ambient colours
+    let ambient = Color3 {
+        red: lighting.ambient.red.max(lighting.outdoor_ambient.red),
+        green: lighting.ambient.green.max(lighting.outdoor_ambient.green),
+        blue: lighting.ambient.blue.max(lighting.outdoor_ambient.blue),
+    };
+
+    let mut keyvalues = vec![
+        ("_light", format!("255 255 255 {}", brightness * LIGHT_INTENSITY)),
+        ("_lightHDR", "-1 -1 -1 1".to_string()),
+        ("_ambient", format!("{} {} {} {}", ambient.red, ambient.green, ambient.blue, AMBIENT_INTENSITY)),
+        ("_ambientHDR", "-1 -1 -1 1".to_string()),
+        ("SunSpreadAngle", (lighting.shadow_softness * 25.0).to_string()),
+    ];
+    // Light shines away from the sun
+    keyvalues.extend(light_angles(Vector3 { x: -light_source.x, y: -light_source.y, z: -light_source.z }));
+
+    Entity {
+        id,
+        classname: "light_environment",
+        keyvalues,
+        origin: Some(origin),
+        solids: Vec::new(),
+        connections: Vec::new(),
+    }
+}
+
+/// Places a spawn point entity on top of a spawn location, facing the same way as the spawn location
+fn spawn_point(spawn: Part, id: u32, classname: &'static str, mut keyvalues: Vec<(&'static str, String)>, map_scale: f64) -> Entity<'static> {
+    let mut origin = to_source_coordinates((Vector3 { x: 0.0, y: spawn.size.y / 2.0, z: 0.0 } * spawn.cframe) * map_scale);
+    origin[2] += 1.0;   // Keep players from getting stuck in the spawn location
+    let look_vector = to_source_coordinates(spawn.cframe.vector_to_world_space(Vector3 { x: 0.0, y: 0.0, z: -1.0 }));
+    keyvalues.push(("angles", format!("0 {} 0", look_vector[1].atan2(look_vector[0]).to_degrees())));
+    Entity {
+        id,
+        classname,
+        keyvalues,
+        origin: Some(origin),
+        solids: Vec::new(),
+        connections: Vec::new(),
+    }
+}
+
+/// Displaces a face of a spherical part's bounding box onto the part's (ellipsoid) surface
+fn sphere_displacement(part: Part, face: [Vector3; 4], power: u32, map_scale: f64) -> Displacement {
+    let half_size = part.size / 2.0;
+    let outward_normal = (face[0] - face[1]).cross(face[2] - face[1]).unit();
+
+    // Source engine lays out displacement vertices starting at the start position, in rows along the first clockwise edge of the face
+    let start = face[0];
+    let mut corners = [face[1], face[2], face[3]];
+    corners.sort_by(|left, right| (*left - start).magnitude().total_cmp(&(*right - start).magnitude()));
+    let [next, previous, opposite] = if (start - corners[0]).cross(corners[2] - corners[0]).dot(outward_normal) > 0.0 {
+        corners
+    } else {
+        [corners[1], corners[0], corners[2]]
+    };
+
+    let vertex_count = (1 << power) + 1;
+    let mut normals = Vec::with_capacity(vertex_count);
+    let mut distances = Vec::with_capacity(vertex_count);
+    for row in 0..vertex_count {
+        let row_fraction = (row as f64) / ((vertex_count - 1) as f64);
+        let row_start = start + (next - start) * row_fraction;
+        let row_end = previous + (opposite - previous) * row_fraction;
+
+        let mut row_normals = Vec::with_capacity(vertex_count);
+        let mut row_distances = Vec::with_capacity(vertex_count);
+        for column in 0..vertex_count {
+            let vertex = row_start + (row_end - row_start) * ((column as f64) / ((vertex_count - 1) as f64));
+            let surface_point = ((vertex / part.cframe) / half_size).unit() * half_size * part.cframe;
+            let offset = Vector3::from_array(to_source_coordinates((surface_point - vertex) * map_scale));
+            let distance = offset.magnitude();
+            if distance > 0.0 {
+                row_normals.push((offset / distance).array());
+            } else {
+                row_normals.push(to_source_coordinates(outward_normal));
+            }
+            row_distances.push(distance);
+        }
+        normals.push(row_normals);
+        distances.push(row_distances);
+    }
+
+    Displacement {
+        power,
+        start_position: to_source_coordinates(start * map_scale),
+        normals,
+        distances,
+        face_normal: to_source_coordinates(outward_normal),
+    }
+}
+
 fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingBox, map_scale: f64, texture_map: &mut TextureMap<RobloxTexture>) -> [Solid; 6] {
     [
         Solid {
@@ -738,6 +1858,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX+X",
                 size: Vector3 {
                     x: 1.0,
@@ -757,7 +1879,9 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         },
         Solid {
             id: {
@@ -768,6 +1892,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX+Y",
                 size: Vector3 {
                     x: (bounding_box.x_max - bounding_box.x_min).abs(),
@@ -787,7 +1913,9 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         },
         Solid {
             id: {
@@ -798,6 +1926,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX+Z",
                 size: Vector3 {
                     x: (bounding_box.x_max - bounding_box.x_min).abs(),
@@ -817,7 +1947,9 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         },
         Solid {
             id: {
@@ -828,6 +1960,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX-X",
                 size: Vector3 {
                     x: 1.0,
@@ -847,7 +1981,9 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         },
         Solid {
             id: {
@@ -858,6 +1994,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX-Y",
                 size: Vector3 {
                     x: (bounding_box.x_max - bounding_box.x_min).abs(),
@@ -877,7 +2015,9 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         },
         Solid {
             id: {
@@ -888,6 +2028,8 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 part_type: PartType::Part,
                 shape: PartShape::Block,
                 is_detail: false,
+                is_self_illuminated: false,
+                visgroup: None,
                 referent: "SKYBOX-Z",
                 size: Vector3 {
                     x: (bounding_box.x_max - bounding_box.x_min).abs(),
@@ -907,7 +2049,177 @@ fn generate_skybox(part_id: &mut u32, side_id: &mut u32, bounding_box: BoundingB
                 reflectance: 0.0,
                 material: Material::Custom { texture: "tools/toolsskybox", fill: false, generate: false, size_x: 512, size_y: 512 },
                 decals: [None, None, None, None, None, None],
-            }, side_id, map_scale, false, texture_map),
+                overlays: [None, None, None, None, None, None],
+            }, side_id, map_scale, false, 0, SphereMode::Skip, texture_map),
+            visgroups: Vec::new(),
         }
     ]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lighting(clock_time: f64, geographic_latitude: f64) -> Lighting {
+        Lighting {
+            clock_time,
+            geographic_latitude,
+            ambient: Color3 { red: 70, green: 70, blue: 70 },
+            outdoor_ambient: Color3 { red: 128, green: 128, blue: 128 },
+            brightness: 2.0,
+            shadow_softness: 0.2,
+        }
+    }
+
+    /// Pitch, yaw, and brightness of a light_environment
+    fn sun(clock_time: f64, geographic_latitude: f64) -> (f64, f64, f64) {
+        let entity = light_environment(lighting(clock_time, geographic_latitude), 1, [0.0; 3]);
+        let keyvalue = |key: &str| entity.keyvalues.iter().find(|(name, _)| *name == key).map(|(_, value)| value.clone()).unwrap();
+        let angles = keyvalue("angles").split(' ').map(|angle| angle.parse::<f64>().unwrap()).collect::<Vec<_>>();
+        let light = keyvalue("_light").rsplit(' ').next().unwrap().parse::<f64>().unwrap();
+        assert!((angles[0] + keyvalue("pitch").parse::<f64>().unwrap()).abs() < 1e-9, "angles pitch is the inverse of pitch");
+        (keyvalue("pitch").parse().unwrap(), angles[1], light / LIGHT_INTENSITY)
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn sun_angles() {
+        // Straight down at noon on the equator
+        let (pitch, _, brightness) = sun(12.0, 0.0);
+        assert_near(pitch, -90.0);
+        assert_near(brightness, 2.0);
+
+        // Rising in the east (+X), shining west
+        let (pitch, yaw, _) = sun(6.0, 0.0);
+        assert_near(pitch, 0.0);
+        assert_near(yaw.rem_euclid(360.0), 180.0);
+
+        // At noon at 45° latitude the sun stands 45° above the southern horizon (+Z), shining north (-Z, which is +Y in Source)
+        let (pitch, yaw, _) = sun(12.0, 45.0);
+        assert_near(pitch, -45.0);
+        assert_near(yaw, 90.0);
+    }
+
+    #[test]
+    fn moon_at_night() {
+        let (pitch, _, brightness) = sun(0.0, 0.0);
+        assert_near(pitch, -90.0);
+        assert_near(brightness, 0.2);
+    }
+
+    fn light_part(size: Vector3) -> Part<'static> {
+        Part {
+            part_type: PartType::Part,
+            shape: PartShape::Block,
+            is_detail: false,
+            is_self_illuminated: false,
+            visgroup: None,
+            referent: "LIGHT",
+            size,
+            cframe: CFrame {
+                position: Vector3 { x: 1.0, y: 2.0, z: 3.0 },
+                rot_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            },
+            color: Color3::white(),
+            transparency: 0.0,
+            reflectance: 0.0,
+            material: Material::Plastic,
+            decals: [None; 6],
+            overlays: [None; 6],
+        }
+    }
+
+    fn light(light_type: LightType) -> Light<'static> {
+        Light { light_type, part: "LIGHT", color: Color3::white(), brightness: 2.0, range: 16.0, attachment: None }
+    }
+
+    /// Summed brightness of light entities
+    fn total_brightness(entities: &[Entity]) -> f64 {
+        entities.iter()
+            .map(|entity| {
+                let (_, light) = entity.keyvalues.iter().find(|(name, _)| *name == "_light").unwrap();
+                light.rsplit(' ').next().unwrap().parse::<f64>().unwrap() / LIGHT_INTENSITY
+            })
+            .sum()
+    }
+
+    #[test]
+    fn point_light_at_part() {
+        let entities = light_entities(light(LightType::Point), light_part(Vector3 { x: 2.0, y: 2.0, z: 2.0 }), &mut 0, 10.0);
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].origin, Some(to_source_coordinates(Vector3 { x: 10.0, y: 20.0, z: 30.0 })));
+        assert_near(total_brightness(&entities), 2.0);
+    }
+
+    #[test]
+    fn lights_at_attachments() {
+        // Attachment 1 stud to the right, turned 90° so that its front (-Z) faces +X of the part
+        let attachment = CFrame {
+            position: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+            rot_matrix: [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]],
+        };
+        let mut part = light_part(Vector3 { x: 2.0, y: 2.0, z: 2.0 });
+        part.cframe.rot_matrix = [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]];    // Part's +X faces -Z
+        let origin = Some(to_source_coordinates(Vector3 { x: 10.0, y: 20.0, z: 20.0 }));
+
+        let point = light_entities(Light { attachment: Some(attachment), ..light(LightType::Point) }, part, &mut 0, 10.0);
+        assert_eq!(point.len(), 1);
+        assert_eq!(point[0].origin, origin);
+
+        let spot = light_entities(Light { attachment: Some(attachment), ..light(LightType::Spot { angle: 90.0, face: 5 }) }, part, &mut 0, 10.0);
+        assert_eq!(spot.len(), 1);
+        assert_eq!(spot[0].origin, origin);
+        // Shining along the part's +X, which is -Z in Roblox and +Y in Source
+        assert!(spot[0].keyvalues.contains(&("angles", format!("{} {} 0", -0.0, 90.0))));
+    }
+
+    #[test]
+    fn surface_light_brightness_is_independent_of_size() {
+        let surface = light(LightType::Surface { angle: 90.0, face: 1 });
+        let small = light_entities(surface, light_part(Vector3 { x: 1.0, y: 1.0, z: 1.0 }), &mut 0, 10.0);
+        let large = light_entities(surface, light_part(Vector3 { x: 100.0, y: 1.0, z: 10.0 }), &mut 0, 10.0);
+        assert_eq!(small.len(), 1);
+        assert_eq!(large.len(), SURFACE_LIGHT_MAX_ROW * 3);
+        assert_near(total_brightness(&small), 2.0);
+        assert_near(total_brightness(&large), 2.0);
+    }
+
+    #[test]
+    fn light_environment_inside_map() {
+        let bounding_box = BoundingBox { x_min: -10.0, x_max: 10.0, y_min: 0.0, y_max: 20.0, z_min: -5.0, z_max: 15.0 };
+        assert_eq!(light_environment_origin(bounding_box, None, 10.0), Vector3 { x: 0.0, y: 19.9, z: 5.0 });
+        assert_eq!(light_environment_origin(bounding_box, Some(5.0), 10.0), Vector3 { x: 0.0, y: 20.1, z: 5.0 });
+        // Skybox clearance under 1 hammer unit, the skybox's top starts at 20.05
+        assert_near(light_environment_origin(bounding_box, Some(0.05), 10.0).y, 19.95);
+        assert_near(light_environment_origin(bounding_box, Some(0.0), 10.0).y, 19.9);
+    }
+
+    #[test]
+    fn right_surface_on_positive_x() {
+        let surface = |texture| Some(Material::Custom { texture, fill: false, generate: true, size_x: 64, size_y: 64 });
+        let mut part = light_part(Vector3 { x: 2.0, y: 2.0, z: 2.0 });
+        part.decals[0] = surface("weld");    // RightSurface
+        part.decals[3] = surface("glue");    // LeftSurface
+        let mut texture_map = TextureMap::new();
+        let sides = decompose_part(part, &mut 0, 10.0, false, 16, SphereMode::Skip, &mut texture_map);
+        let texture_facing = |x: f64| sides.iter()
+            .find(|side| {
+                let [a, b, c] = side.plane.map(Vector3::from_array);
+                (a - b).cross(c - b).unit().x * x > 0.5
+            })
+            .and_then(|side| texture_map.get_texture(side.texture))
+            .map(|texture| texture.material)
+            .unwrap();
+        assert_eq!(texture_facing(1.0), surface("weld").unwrap());
+        assert_eq!(texture_facing(-1.0), surface("glue").unwrap());
+    }
+
+    #[test]
+    fn ambient_uses_brightest_colour() {
+        let entity = light_environment(lighting(12.0, 0.0), 1, [0.0; 3]);
+        assert!(entity.keyvalues.contains(&("_ambient", format!("128 128 128 {}", AMBIENT_INTENSITY))));
+    }
+}
@@ -1,12 +1,31 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use roxmltree::Node;
-use crate::rbx::{Part, Color3, PartShape, Material, PartType, Vector3, CFrame};
+use crate::rbx::{Part, Color3, PartShape, Material, PartType, Vector3, CFrame, Instance, InstanceTree, Property, Light, LightType, Lighting, Sky, MaterialVariant, Decal};
+use crate::vmf::Visgroup;
+
+const DECAL_FRONT: usize = 5;
+const DECAL_BACK: usize = 2;
+const DECAL_TOP: usize = 1;
+const DECAL_BOTTOM: usize = 4;
+const DECAL_RIGHT: usize = 0;
+const DECAL_LEFT: usize = 3;
+
+/// Surface properties, with the decal index they apply to
+const SURFACES: [(&str, usize); 6] = [
+    ("FrontSurface", DECAL_FRONT),
+    ("BackSurface", DECAL_BACK),
+    ("TopSurface", DECAL_TOP),
+    ("BottomSurface", DECAL_BOTTOM),
+    ("RightSurface", DECAL_RIGHT),
+    ("LeftSurface", DECAL_LEFT),
+];
 
 /// Convenience trait; Provides methods for searching for specific children of a node
 pub trait NodeExtensions<'a> {
     type Output;
 
     fn get_child_with_name(self, tag_name: &'a str) -> Option<Self::Output>;
-    fn get_child_with_attribute(self, tag_name: &'a str, attribute_name: &'a str, attribute_value: &'a str) -> Option<Self::Output>;
     fn get_child_text(self, tag_name: &'a str) -> Option<&'a str>;
 }
 
@@ -19,221 +38,813 @@ impl<'a, 'input> NodeExtensions<'a> for Node<'a, 'input> {
             .next()
     }
 
-    fn get_child_with_attribute(self, tag_name: &str, attribute_name: &str, attribute_value: &str) -> Option<Node<'a, 'input>> {
-        self.children()
-            .filter(|node| node.tag_name().name().eq(tag_name))
-            .filter(|node| node.attribute(attribute_name) == Some(&attribute_value))
-            .next()
-    }
-
     fn get_child_text(self, tag_name: &'a str) -> Option<&'a str> {
         self.get_child_with_name(tag_name)?.text()
     }
 }
 
-/// Recursively parses XML
-/// Expects machine-generated RBXLX files as input, and skips any malformed items.
-pub fn parse_xml<'a>(node: Node<'a, '_>, parts: &mut Vec<Part<'a>>, is_detail: bool, decal_size: u64) {
-    match node.attribute("class") {
-        Some(class @ "Part") | Some(class @ "SpawnLocation") | Some(class @ "TrussPart") | Some(class @ "WedgePart") => {
-            let option: Option<()> = try {
-                let referent = node.attribute("referent")?;
-                let properties = node.get_child_with_name("Properties")?;
-
-                let size_node = properties.get_child_with_attribute("Vector3", "name", "size")?;
-                let position_node = properties.get_child_with_attribute("CoordinateFrame", "name", "CFrame")?;
-
-                let color = Color3::from(
-                    properties.get_child_with_name("Color3uint8")?
-                        .text()?
-                        .parse::<u32>()
-                        .ok()?
-                );
-
-                let transparency = properties.get_child_with_attribute("float", "name", "Transparency")?
-                    .text()?
-                    .parse::<f64>()
-                    .ok()?;
-
-                let reflectance = properties.get_child_with_attribute("float", "name", "Reflectance")?
-                    .text()?
-                    .parse::<f64>()
-                    .ok()?;
-
-                let material = Material::from_id(
-                    properties.get_child_with_attribute("token", "name", "Material")?
-                        .text()?
-                        .parse::<u32>()
-                        .ok()?
-                )?;
-
-                // Truss parts do not have a shape field, so this field is not required
-                let shape = match properties.get_child_with_attribute("token", "name", "shape")
-                    .as_ref()
-                    .and_then(Node::text)
-                    .and_then(|text| text.parse::<u32>().ok())
-                {
-                    Some(0) => PartShape::Sphere,
-                    Some(2) => PartShape::Cylinder,
-                    Some(1) | _ => PartShape::Block,  // Default to block
-                };
+/// Recursively reads the Items of an XML document into an instance tree
+/// Expects machine-generated RBXLX files as input, and skips properties of unsupported types.
+pub fn parse_xml(node: Node, tree: &mut InstanceTree, parent: Option<usize>) {
+    for item in node.children().filter(|child| child.tag_name().name() == "Item") {
+        let mut instance = Instance::new(
+            item.attribute("class").unwrap_or_default().to_string(),
+            item.attribute("referent").unwrap_or_default().to_string(),
+        );
+        if let Some(properties) = item.get_child_with_name("Properties") {
+            for property in properties.children().filter(Node::is_element) {
+                if let (Some(name), Some(value)) = (property.attribute("name"), parse_property(property)) {
+                    match (name, value) {
+                        ("Name", Property::String(name)) => instance.name = name,
+                        (name, value) => { instance.properties.insert(name.to_string(), value); }
+                    }
+                }
+            }
+        }
+        let index = tree.insert(instance, parent);
+        parse_xml(item, tree, Some(index));
+    }
+}
+
+/// Parses a single XML property, returns None if the property is malformed or of an unsupported type
+fn parse_property(node: Node) -> Option<Property> {
+    Some(match node.tag_name().name() {
+        "CoordinateFrame" => Property::CoordinateFrame(CFrame {
+            position: Vector3 {
+                x: node.get_child_text("X")?.parse().ok()?,
+                y: node.get_child_text("Y")?.parse().ok()?,
+                z: node.get_child_text("Z")?.parse().ok()?,
+            },
+            rot_matrix: [
+                [node.get_child_text("R00")?.parse().ok()?, node.get_child_text("R10")?.parse().ok()?, node.get_child_text("R20")?.parse().ok()?],
+                [node.get_child_text("R01")?.parse().ok()?, node.get_child_text("R11")?.parse().ok()?, node.get_child_text("R21")?.parse().ok()?],
+                [node.get_child_text("R02")?.parse().ok()?, node.get_child_text("R12")?.parse().ok()?, node.get_child_text("R22")?.parse().ok()?],
+            ],
+        }),
+        "Vector3" => Property::Vector3(Vector3 {
+            x: node.get_child_text("X")?.parse().ok()?,
+            y: node.get_child_text("Y")?.parse().ok()?,
+            z: node.get_child_text("Z")?.parse().ok()?,
+        }),
+        "Color3uint8" => Property::Color3uint8(Color3::from(node.text()?.parse::<u32>().ok()?)),
+        "Color3" => Property::Color3(Color3::from_floats(
+            node.get_child_text("R")?.parse().ok()?,
+            node.get_child_text("G")?.parse().ok()?,
+            node.get_child_text("B")?.parse().ok()?,
+        )),
+        "token" => Property::Token(node.text()?.parse().ok()?),
+        "Content" => Property::Content(node.get_child_text("url").unwrap_or_default().to_string()),    // Empty content is stored as <null></null>
+        "BinaryString" => Property::BinaryString(node.text().unwrap_or_default().trim().to_string()),
+        "SharedString" => Property::SharedString(node.text().unwrap_or_default().to_string()),
+        "bool" => Property::Bool(node.text()? == "true"),
+        "int" | "int64" => Property::Int(node.text()?.parse().ok()?),
+        "float" | "double" => Property::Float(node.text()?.parse().ok()?),
+        "string" | "ProtectedString" => Property::String(node.text().unwrap_or_default().to_string()),
+        _ => return None
+    })
+}
+
+/// Settings for extracting parts, shared by every level of the instance tree
+pub struct PartSettings<'a> {
+    pub decal_size: u64,
+    pub material_variants: &'a [MaterialVariant<'a>],
+    /// Read parts of Tools and Accessories
+    pub include_tools: bool,
+    /// Read parts of character models, Models with a Humanoid
+    pub include_characters: bool,
+    /// Instances removed by filters, these are skipped along with their descendants
+    pub filtered: &'a HashSet<usize>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FilterKind {
+    /// Glob pattern on the full path of an instance (E.g. "Workspace/Map/**")
+    Path,
+    /// Exact class name
+    Class,
+    /// Glob pattern on the name of an instance
+    Name,
+}
+
+/// Filter on instances
+pub struct InstanceFilter {
+    pub kind: FilterKind,
+    pub pattern: String,
+    glob: Glob,
+    /// Number of instances removed by this filter; Only counted for exclude filters, include filters are counted together
+    pub removed: usize,
+}
+
+impl InstanceFilter {
+    pub fn new(kind: FilterKind, pattern: &str) -> Self {
+        InstanceFilter { kind, pattern: pattern.to_string(), glob: Glob::new(pattern), removed: 0 }
+    }
+
+    /// Tests an instance, with its full path (E.g. "Workspace/Map/Floor")
+    pub fn matches(&self, instance: &Instance, path: &str) -> bool {
+        match self.kind {
+            FilterKind::Path => self.glob.matches(path),
+            FilterKind::Class => instance.class_name == self.pattern,
+            FilterKind::Name => self.glob.matches(&instance.name),
+        }
+    }
+}
+
+impl Display for InstanceFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            FilterKind::Path => write!(f, "path \"{}\"", self.pattern),
+            FilterKind::Class => write!(f, "class \"{}\"", self.pattern),
+            FilterKind::Name => write!(f, "name \"{}\"", self.pattern),
+        }
+    }
+}
+
+/// Include and exclude filters on instances
+/// If there are include filters, only instances matching one of them or inside of a matching instance are kept; Their ancestors are kept to reach them.
+/// Exclude filters remove matching instances along with their descendants, and take precedence over include filters.
+#[derive(Default)]
+pub struct InstanceFilters {
+    pub include: Vec<InstanceFilter>,
+    pub exclude: Vec<InstanceFilter>,
+    /// Number of instances removed for not matching any of the include filters
+    pub include_removed: usize,
+}
+
+impl InstanceFilters {
+    /// Returns the instances removed by filters below the given roots, and counts the removed instances for each exclude filter, and for the include filters together
+    pub fn apply(&mut self, tree: &InstanceTree, roots: &[usize]) -> HashSet<usize> {
+        let mut filtered = HashSet::new();
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return filtered;
+        }
+        for root in roots {
+            let mut path = tree.get(*root).parent.map(|parent| instance_path(tree, parent)).unwrap_or_default();
+            self.apply_instance(tree, *root, &mut path, self.include.is_empty(), &mut filtered);
+        }
+        filtered
+    }
+
+    /// Recursively filters an instance, the path of its parent is extended while descending
+    /// Returns whether the instance or any of its descendants are kept.
+    fn apply_instance(&mut self, tree: &InstanceTree, index: usize, path: &mut String, is_included: bool, filtered: &mut HashSet<usize>) -> bool {
+        let instance = tree.get(index);
+        let parent_length = path.len();
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(&instance.name);
+
+        let is_kept = if let Some(filter) = self.exclude.iter_mut().find(|filter| filter.matches(instance, path)) {
+            filter.removed += instance_count(tree, index);
+            filtered.insert(index);
+            false
+        } else {
+            let is_included = is_included || self.include.iter().any(|filter| filter.matches(instance, path));
+            let mut is_kept = is_included;
+            for child in &instance.children {
+                is_kept |= self.apply_instance(tree, *child, path, is_included, filtered);
+            }
+            if !is_kept {
+                self.include_removed += 1;
+                filtered.insert(index);
+            }
+            is_kept
+        };
 
-                const DECAL_FRONT: usize = 5;
-                const DECAL_BACK: usize = 2;
-                const DECAL_TOP: usize = 1;
-                const DECAL_BOTTOM: usize = 4;
-                const DECAL_RIGHT: usize = 0;
-                const DECAL_LEFT: usize = 3;
+        path.truncate(parent_length);
+        is_kept
+    }
+}
+
+/// Full path of an instance, the names of its ancestors and itself separated by '/' (E.g. "Workspace/Map/Floor")
+fn instance_path(tree: &InstanceTree, index: usize) -> String {
+    let mut names = vec![&*tree.get(index).name];
+    let mut parent = tree.get(index).parent;
+    while let Some(ancestor) = parent {
+        names.push(&tree.get(ancestor).name);
+        parent = tree.get(ancestor).parent;
+    }
+    names.reverse();
+    names.join("/")
+}
+
+/// Number of instances in the subtree of an instance, including itself
+fn instance_count(tree: &InstanceTree, index: usize) -> usize {
+    1 + tree.get(index).children.iter().map(|child| instance_count(tree, *child)).sum::<usize>()
+}
+
+/// Glob pattern on instance names and paths
+/// '?' matches any one character and '*' any number of characters, except for '/'; '**' also matches across '/', and "**/" matches zero or more whole path components
+struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Self {
+        Glob { pattern: pattern.chars().collect() }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        // Results are memoized by pattern position and text byte offset, so that repeated wildcards do not backtrack exponentially
+        let mut memo = vec![None; (self.pattern.len() + 1) * (text.len() + 1)];
+        self.matches_from(0, text, 0, &mut memo)
+    }
+
+    fn matches_from(&self, pattern_index: usize, text: &str, text_index: usize, memo: &mut [Option<bool>]) -> bool {
+        let key = pattern_index * (text.len() + 1) + text_index;
+        if let Some(result) = memo[key] {
+            return result;
+        }
+        let rest = &text[text_index..];
+        let result = match &self.pattern[pattern_index..] {
+            [] => rest.is_empty(),
+            ['*', '*', '/', ..] => self.matches_from(pattern_index + 3, text, text_index, memo)
+                || rest.match_indices('/').any(|(i, _)| self.matches_from(pattern_index + 3, text, text_index + i + 1, memo)),
+            ['*', '*', ..] => (text_index..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| self.matches_from(pattern_index + 2, text, i, memo)),
+            ['*', ..] => (text_index..=rest.find('/').map_or(text.len(), |i| text_index + i))
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| self.matches_from(pattern_index + 1, text, i, memo)),
+            ['?', ..] => rest.chars().next()
+                .is_some_and(|c| c != '/' && self.matches_from(pattern_index + 1, text, text_index + c.len_utf8(), memo)),
+            [c, ..] => rest.starts_with(*c) && self.matches_from(pattern_index + 1, text, text_index + c.len_utf8(), memo),
+        };
+        memo[key] = Some(result);
+        result
+    }
+}
+
+/// Returns the instances at a path of instance names separated by '/', starting with the name of a root instance (E.g. "Workspace/Map")
+pub fn find_instances(tree: &InstanceTree, path: &str) -> Vec<usize> {
+    let mut names = path.split('/').filter(|name| !name.is_empty());
+    let Some(root_name) = names.next() else { return Vec::new() };
+    let mut indices: Vec<usize> = tree.roots().filter(|index| tree.get(*index).name == root_name).collect();
+    for name in names {
+        indices = indices.into_iter()
+            .flat_map(|index| tree.get(index).children.iter().copied())
+            .filter(|child| tree.get(*child).name == name)
+            .collect();
+    }
+    indices
+}
+
+/// Returns the instances parts are read from; The Workspace, or every root of files without one (such as model files), and the given included instances
+/// Instances inside of another returned instance are left out, so that they are only read once.
+pub fn traversal_roots(tree: &InstanceTree, included: &[usize]) -> Vec<usize> {
+    let mut roots: Vec<usize> = tree.roots().filter(|index| tree.get(*index).class_name == "Workspace").collect();
+    if roots.is_empty() {
+        roots = tree.roots().collect();
+    }
+    roots.extend(included);
+    roots.sort_unstable();
+    roots.dedup();
 
+    let is_nested = |index: usize| {
+        let mut parent = tree.get(index).parent;
+        while let Some(ancestor) = parent {
+            if roots.contains(&ancestor) {
+                return true;
+            }
+            parent = tree.get(ancestor).parent;
+        }
+        false
+    };
+    roots.iter().copied().filter(|index| !is_nested(*index)).collect()
+}
+
+/// Recursively extracts parts from an instance tree, and Models and Folders containing parts as visgroups
+/// Skips any malformed items.
+pub fn parse_parts<'a>(tree: &'a InstanceTree, index: usize, parts: &mut Vec<Part<'a>>, visgroups: &mut Vec<Visgroup<'a>>, visgroup: Option<u32>, is_detail: bool, settings: &PartSettings) {
+    if settings.filtered.contains(&index) {
+        return;
+    }
+    let instance = tree.get(index);
+    match &*instance.class_name {
+        class @ ("Part" | "SpawnLocation" | "TrussPart" | "WedgePart" | "CornerWedgePart") => {
+            let option: Option<()> = try {
                 let mut decals = [None; 6];
+                let mut overlays = [None; 6];
 
-                fn decal_for_side(properties: Node, decals: &mut [Option<Material>; 6], side_name: &str, side_enum: usize) {
-                    if let Some(surface) = properties.get_child_with_attribute("token", "name", side_name).and_then(|node| node.text()) {
-                        let decal = match surface.parse() {
-                            Ok(3u8) => Some(Material::Custom { texture: "studs", fill: false, generate: true, size_x: 32, size_y: 32 }),    // Studs,    TODO: other surfaces
-                            Ok(4u8) => Some(Material::Custom { texture: "inlet", fill: false, generate: true, size_x: 32, size_y: 32 }),    // Inlet,
-                            _ => None
-                        };
-                        decals[side_enum] = decal;
+                for (side_name, side_enum) in SURFACES {
+                    if let Some(surface) = instance.get_token(side_name) {
+                        decals[side_enum] = surface_decal(surface);
                     }
                 }
-                decal_for_side(properties, &mut decals, "FrontSurface", DECAL_FRONT);
-                decal_for_side(properties, &mut decals, "BackSurface", DECAL_BACK);
-                decal_for_side(properties, &mut decals, "TopSurface", DECAL_TOP);
-                decal_for_side(properties, &mut decals, "BottomSurface", DECAL_BOTTOM);
-                decal_for_side(properties, &mut decals, "RightSurface", DECAL_RIGHT);
-                decal_for_side(properties, &mut decals, "LeftSurface", DECAL_LEFT);
-
-                node.children()
-                    .filter(|child_node| child_node.tag_name().name() == "Item" && child_node.attribute("class") == Some(&"Decal"))
-                    .filter_map(|child_node| child_node.get_child_with_name("Properties"))
-                    .filter_map(|properties| {
-                        if let (Some(face), Some(texture)) = (
-                            properties.get_child_with_attribute("token", "name", "Face").as_ref().and_then(Node::text).and_then(|text| text.parse::<u8>().ok()),
-                            properties.get_child_with_attribute("Content", "name", "Texture").and_then(|node| node.get_child_with_name("url")).as_ref().and_then(Node::text)
-                        ) {
-                            Some((face, texture))
-                        } else {
-                            None
-                        }
-                    })
-                    .for_each(|(face, texture)| {
+
+                // Decals and Textures removed by filters are left out, like any other filtered instance
+                let children = instance.children.iter()
+                    .filter(|child| !settings.filtered.contains(*child))
+                    .map(|child| tree.get(*child))
+                    .collect::<Vec<_>>();
+
+                for child in children.iter().filter(|child| child.class_name == "Decal") {
+                    if let (Some(face), Some(texture)) = (child.get_token("Face"), child.get_content("Texture")) {
                         if face < 6 {
-                            if let Some(id) = texture.split_once("?id=").and_then(|(_, id)| id.parse::<u64>().ok()) {
-                                if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {   // We can't fetch decals on WASM as a result of CORS limitations     TODO: Host the web-app version on a dedicated webserver that can proxy the requests
-                                    decals[face as usize] = Some(Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 })
-                                } else {
-                                    decals[face as usize] = Some(Material::Decal { id, size_x: decal_size, size_y: decal_size })
-                                }
-                            } else {
-                                decals[face as usize] = Some(Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 })
-                            }
+                            overlays[face as usize] = Some(Decal {
+                                material: decal_material(texture, settings.decal_size),
+                                color: child.get_color3("Color3").unwrap_or(Color3::white()),
+                                transparency: child.get_float("Transparency").unwrap_or(0.0),
+                            })
                         }
-                    });
-
-                node.children()
-                    .filter(|child_node| child_node.tag_name().name() == "Item" && child_node.attribute("class") == Some(&"Texture"))
-                    .filter_map(|child_node| child_node.get_child_with_name("Properties"))
-                    .filter_map(|properties| {
-                        if let (Some(face), Some(texture), Some(studs_per_u), Some(studs_per_v), Some(offset_studs_per_u), Some(offset_studs_per_v)) = (
-                            properties.get_child_with_attribute("token", "name", "Face").as_ref().and_then(Node::text).and_then(|text| text.parse::<u8>().ok()),
-                            properties.get_child_with_attribute("Content", "name", "Texture").and_then(|node| node.get_child_with_name("url")).as_ref().and_then(Node::text),
-                            properties.get_child_with_attribute("float", "name", "StudsPerTileU").as_ref().and_then(Node::text).and_then(|text| text.parse::<f64>().ok()),
-                            properties.get_child_with_attribute("float", "name", "StudsPerTileV").as_ref().and_then(Node::text).and_then(|text| text.parse::<f64>().ok()),
-                            properties.get_child_with_attribute("float", "name", "OffsetStudsU").as_ref().and_then(Node::text).and_then(|text| text.parse::<f64>().ok()),
-                            properties.get_child_with_attribute("float", "name", "OffsetStudsV").as_ref().and_then(Node::text).and_then(|text| text.parse::<f64>().ok()),
-                        ) {
-                            Some((face, texture, studs_per_u.abs(), studs_per_v.abs(), offset_studs_per_u, offset_studs_per_v))
-                        } else {
-                            None
-                        }
-                    })
-                    .for_each(|(face, texture, studs_per_u, studs_per_v, offset_u, offset_v)| {
+                    }
+                }
+
+                for child in children.iter().filter(|child| child.class_name == "Texture") {
+                    if let (Some(face), Some(texture), Some(studs_per_u), Some(studs_per_v), Some(offset_u), Some(offset_v)) = (
+                        child.get_token("Face"),
+                        child.get_content("Texture"),
+                        child.get_float("StudsPerTileU"),
+                        child.get_float("StudsPerTileV"),
+                        child.get_float("OffsetStudsU"),
+                        child.get_float("OffsetStudsV"),
+                    ) {
                         if face < 6 {
-                            if let Some(id) = texture.split_once("?id=").and_then(|(_, id)| id.parse::<u64>().ok()) {
-                                decals[face as usize] = Some(Material::Texture { id, size_x: decal_size, size_y: decal_size, studs_per_u, studs_per_v, offset_u, offset_v })
-                            } else {
-                                decals[face as usize] = Some(Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 })
-                            }
+                            decals[face as usize] = Some(texture_material(texture, settings.decal_size, studs_per_u.abs(), studs_per_v.abs(), offset_u, offset_v))
                         }
-                    });
+                    }
+                }
 
                 if class == "SpawnLocation" {
-                    decals[DECAL_TOP] = Some(Material::Custom { texture: "spawnlocation", fill: true, generate: true, size_x: 256, size_y: 256 })
+                    decals[DECAL_TOP] = Some(SPAWNLOCATION_DECAL)
                 }
 
-                let part_type = match class {
-                    "Part" => PartType::Part,
-                    "SpawnLocation" => PartType::SpawnLocation,
-                    "TrussPart" => PartType::Truss,
-                    "WedgePart" => PartType::Wedge,
-                    _ => unreachable!() // We match on class earlier, and only permit the above three options
-                };
-
                 parts.push(Part {
-                    part_type,
-                    shape,
+                    part_type: part_type(instance)?,
+                    shape: part_shape(instance.get_token("shape")),  // Truss parts do not have a shape field, so this field is not required
                     is_detail,
-                    referent,
-                    size: Vector3 {
-                        x: size_node.get_child_text("X")?.parse().ok()?,
-                        y: size_node.get_child_text("Y")?.parse().ok()?,
-                        z: size_node.get_child_text("Z")?.parse().ok()?,
-                    },
-                    cframe: CFrame {
-                        position: Vector3 {
-                            x: position_node.get_child_text("X")?.parse().ok()?,
-                            y: position_node.get_child_text("Y")?.parse().ok()?,
-                            z: position_node.get_child_text("Z")?.parse().ok()?,
-                        },
-                        rot_matrix: [
-                            [position_node.get_child_text("R00")?.parse().ok()?, position_node.get_child_text("R10")?.parse().ok()?, position_node.get_child_text("R20")?.parse().ok()?],
-                            [position_node.get_child_text("R01")?.parse().ok()?, position_node.get_child_text("R11")?.parse().ok()?, position_node.get_child_text("R21")?.parse().ok()?],
-                            [position_node.get_child_text("R02")?.parse().ok()?, position_node.get_child_text("R12")?.parse().ok()?, position_node.get_child_text("R22")?.parse().ok()?],
-                        ],
-                    },
-                    color,
-                    transparency,
-                    reflectance,
-                    material,
+                    is_self_illuminated: false,
+                    visgroup,
+                    referent: &instance.referent,
+                    size: instance.get_vector3("size")?,
+                    cframe: instance.get_cframe("CFrame")?,
+                    color: instance.get_color3uint8("Color3uint8")?,
+                    transparency: instance.get_float("Transparency")?,
+                    reflectance: instance.get_float("Reflectance")?,
+                    material: part_material(instance, settings.material_variants)?,
                     decals,
+                    overlays,
                 });
             };
             if option.is_none() {
-                println!("Skipping malformed Part: {}-{}", node.range().start, node.range().end)
+                println!("Skipping malformed Part: {}", instance.referent)
+            }
+        }
+        // Tools and accessories are carried by players, characters are spawned in by the game
+        "Tool" | "HopperBin" | "Accessory" | "Accoutrement" | "Hat" if !settings.include_tools => {}
+        "Model" if !settings.include_characters && tree.children(index).any(|child| child.class_name == "Humanoid") => {}
+        class @ ("Model" | "Folder") => {
+            let is_model_detail = is_detail
+                | (class == "Model" && (
+                    (instance.name == "func_detail")
+                    | tree.children(index)
+                        .filter(|child| child.class_name == "StringValue")
+                        .any(|child| child.name == "func_detail" || child.get_string("Value") == Some("func_detail"))
+                ));
+
+            // Instance indices are unique, and are reused as visgroup ids
+            let id = index as u32 + 1;
+            let part_count = parts.len();
+            let mut children = Vec::new();
+            for child in &instance.children {
+                parse_parts(tree, *child, parts, &mut children, Some(id), is_model_detail, settings)
             }
+            // Models without any parts are left out
+            if parts.len() > part_count {
+                visgroups.push(Visgroup { id, name: &instance.name, children });
+            }
+        }
+        _ => {
+            for child in &instance.children {
+                parse_parts(tree, *child, parts, visgroups, visgroup, is_detail, settings)
+            }
+        }
+    }
+}
+
+/// Recursively extracts enabled lights from an instance tree, skipping filtered instances
+/// Lights are attached to their parent, or to the parent of their Attachment; Lights that are not attached to a part are skipped when converting.
+pub fn parse_lights<'a>(tree: &'a InstanceTree, index: usize, lights: &mut Vec<Light<'a>>, filtered: &HashSet<usize>) {
+    if filtered.contains(&index) {
+        return;
+    }
+    let instance = tree.get(index);
+    if let ("PointLight" | "SpotLight" | "SurfaceLight", Some(parent)) = (&*instance.class_name, instance.parent) {
+        let option: Option<()> = try {
+            if instance.get_bool("Enabled") != Some(false) {
+                // Attachments position lights relative to their own parent
+                let (parent, attachment) = match tree.get(parent) {
+                    attachment if attachment.class_name == "Attachment" => (attachment.parent?, Some(attachment.get_cframe("CFrame")?)),
+                    _ => (parent, None)
+                };
+                lights.push(Light {
+                    light_type: match &*instance.class_name {
+                        "SpotLight" => LightType::Spot { angle: instance.get_float("Angle")?, face: instance.get_token("Face").filter(|face| *face < 6)? as usize },
+                        "SurfaceLight" => LightType::Surface { angle: instance.get_float("Angle")?, face: instance.get_token("Face").filter(|face| *face < 6)? as usize },
+                        _ => LightType::Point
+                    },
+                    part: &tree.get(parent).referent,
+                    attachment,
+                    color: instance.get_color3("Color")?,
+                    brightness: instance.get_float("Brightness")?,
+                    range: instance.get_float("Range")?,
+                });
+            }
+        };
+        if option.is_none() {
+            println!("Skipping malformed {}: {}", instance.class_name, instance.referent)
         }
-        Some("Model") => {
+    }
+    for child in &instance.children {
+        parse_lights(tree, *child, lights, filtered)
+    }
+}
+
+/// Reads the settings of the Lighting service, if the file has one
+/// Properties missing from the file are set to Roblox's defaults.
+pub fn parse_lighting(tree: &InstanceTree) -> Option<Lighting> {
+    let lighting = tree.roots()
+        .map(|index| tree.get(index))
+        .find(|instance| instance.class_name == "Lighting")?;
+    Some(Lighting {
+        // The XML format only stores TimeOfDay, as a "hh:mm:ss" string
+        clock_time: lighting.get_float("ClockTime")
+            .or_else(|| time_of_day(lighting.get_string("TimeOfDay")?))
+            .unwrap_or(14.0),
+        geographic_latitude: lighting.get_float("GeographicLatitude").unwrap_or(41.733),
+        ambient: lighting.get_color3("Ambient").unwrap_or(Color3 { red: 0, green: 0, blue: 0 }),
+        outdoor_ambient: lighting.get_color3("OutdoorAmbient").unwrap_or(Color3 { red: 128, green: 128, blue: 128 }),
+        brightness: lighting.get_float("Brightness").unwrap_or(2.0),
+        shadow_softness: lighting.get_float("ShadowSoftness").unwrap_or(0.2),
+    })
+}
+
+/// Reads the Sky of the Lighting service, if it has one with all six faces set
+pub fn parse_sky(tree: &InstanceTree) -> Option<Sky<'_>> {
+    let lighting = tree.roots().find(|index| tree.get(*index).class_name == "Lighting")?;
+    let sky = tree.children(lighting).find(|child| child.class_name == "Sky")?;
+    Some(Sky {
+        faces: [
+            sky.get_content("SkyboxBk")?,
+            sky.get_content("SkyboxDn")?,
+            sky.get_content("SkyboxFt")?,
+            sky.get_content("SkyboxLf")?,
+            sky.get_content("SkyboxRt")?,
+            sky.get_content("SkyboxUp")?,
+        ]
+    })
+}
+
+/// Reads the MaterialVariants under the MaterialService, skipping variants without a colour map
+/// Variant textures are converted at the given size.
+pub fn parse_material_variants(tree: &InstanceTree, texture_size: u64) -> Vec<MaterialVariant<'_>> {
+    let mut material_variants = Vec::new();
+    let mut indices: Vec<usize> = tree.roots().filter(|index| tree.get(*index).class_name == "MaterialService").collect();
+    while let Some(index) = indices.pop() {
+        let instance = tree.get(index);
+        if instance.class_name == "MaterialVariant" {
             let option: Option<()> = try {
-                let is_model_detail = is_detail |
-                    (node.get_child_with_name("Properties")?.get_child_with_attribute("string", "name", "Name")?.text() == Some(&"func_detail")) |
-                    node.children()
-                        .filter(|p| {
-                            p.attribute("class")
-                                .map(|s| s == "StringValue")
-                                .unwrap_or(false)
-                        })
-                        .any(|node| {
-                            if let Some(properties) = node.get_child_with_name("Properties") {
-                                (properties.get_child_with_attribute("string", "name", "Name").as_ref().and_then(Node::text) == Some(&"func_detail"))
-                                    | (properties.get_child_with_attribute("string", "name", "Value").as_ref().and_then(Node::text) == Some(&"func_detail"))
-                            } else {
-                                false
-                            }
-                        });
-
-                for child in node.children() {
-                    parse_xml(child, parts, is_model_detail, decal_size)
-                }
+                material_variants.push(MaterialVariant {
+                    name: &instance.name,
+                    base_material: instance.get_token("BaseMaterial")?,
+                    material: Material::Variant {
+                        base_material: instance.get_token("BaseMaterial")?,
+                        color_map: asset_id(instance.get_content("ColorMap")?)?,
+                        normal_map: instance.get_content("NormalMap").and_then(asset_id),
+                        size_x: texture_size,
+                        size_y: texture_size,
+                        studs_per_tile: instance.get_float("StudsPerTile").filter(|studs| *studs > 0.0).unwrap_or(10.0),
+                    },
+                });
             };
             if option.is_none() {
-                println!("Skipping malformed Model: {}-{}", node.range().start, node.range().end)
+                println!("Skipping MaterialVariant without ColorMap: {}", instance.name)
             }
         }
-        _ => {
-            for child in node.children() {
-                parse_xml(child, parts, is_detail, decal_size)
+        indices.extend(&instance.children);
+    }
+    material_variants
+}
+
+/// Returns the material of a part, which is its MaterialVariant if it has one
+fn part_material(instance: &Instance, material_variants: &[MaterialVariant]) -> Option<Material> {
+    let base_material = instance.get_token("Material")?;
+    // The XML format stores the variant name as MaterialVariantSerialized
+    let variant = instance.get_string("MaterialVariantSerialized")
+        .or_else(|| instance.get_string("MaterialVariant"))
+        .filter(|name| !name.is_empty())
+        .and_then(|name| material_variants.iter().find(|variant| variant.name == name && variant.base_material == base_material));
+    match variant {
+        Some(variant) => Some(variant.material),
+        None => Material::from_id(base_material)
+    }
+}
+
+/// Returns the asset id of a content URL, either a "rbxassetid://" URL or an asset URL with an "?id=" parameter
+pub fn asset_id(url: &str) -> Option<u64> {
+    let id = url.strip_prefix("rbxassetid://")
+        .or_else(|| url.split_once("?id=").map(|(_, id)| id))?;
+    id.split('&').next()?.trim().parse().ok()
+}
+
+/// Parses a "hh:mm:ss" time into hours
+fn time_of_day(time: &str) -> Option<f64> {
+    let mut components = time.split(':').map(str::parse::<f64>);
+    let hours = components.next()?.ok()?;
+    let minutes = components.next().unwrap_or(Ok(0.0)).ok()?;
+    let seconds = components.next().unwrap_or(Ok(0.0)).ok()?;
+    Some(hours + minutes / 60.0 + seconds / 3600.0)
+}
+
+const SPAWNLOCATION_DECAL: Material = Material::Custom { texture: "spawnlocation", fill: true, generate: true, size_x: 256, size_y: 256 };
+
+fn part_type(instance: &Instance) -> Option<PartType> {
+    match &*instance.class_name {
+        "Part" => Some(PartType::Part),
+        "SpawnLocation" => Some(PartType::SpawnLocation {
+            team_color: match instance.get_bool("Neutral") {
+                Some(false) => instance.get_int("TeamColor"),
+                _ => None   // Spawns are neutral by default
             }
+        }),
+        "TrussPart" => Some(PartType::Truss),
+        "WedgePart" => Some(PartType::Wedge),
+        "CornerWedgePart" => Some(PartType::CornerWedge),
+        _ => None
+    }
+}
+
+fn part_shape(shape: Option<u32>) -> PartShape {
+    match shape {
+        Some(0) => PartShape::Sphere,
+        Some(2) => PartShape::Cylinder,
+        _ => PartShape::Block,  // Default to block
+    }
+}
+
+/// Returns the texture for a given SurfaceType, if the surface has one
+/// Most surfaces tile once per stud, hinges and motors are marked once in the middle of the face.
+fn surface_decal(surface: u32) -> Option<Material> {
+    let tiled = |texture: &'static str| Some(Material::Custom { texture, fill: false, generate: true, size_x: 32, size_y: 32 });
+    let centered = |texture: &'static str| Some(Material::Custom { texture, fill: true, generate: true, size_x: 64, size_y: 64 });
+    match surface {
+        1 => tiled("glue"),
+        2 => tiled("weld"),
+        3 => tiled("studs"),
+        4 => tiled("inlet"),
+        5 => tiled("universal"),
+        6 => centered("hinge"),
+        7 => centered("motor"),
+        8 => centered("steppingmotor"),
+        _ => None   // Smooth and SmoothNoOutlines
+    }
+}
+
+/// Returns the material for a Decal with the given texture URL
+fn decal_material(texture: &str, decal_size: u64) -> Material {
+    if let Some(id) = asset_id(texture) {
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {   // We can't fetch decals on WASM as a result of CORS limitations     TODO: Host the web-app version on a dedicated webserver that can proxy the requests
+            Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 }
+        } else {
+            Material::Decal { id, size_x: decal_size, size_y: decal_size }
+        }
+    } else {
+        Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 }
+    }
+}
+
+/// Returns the material for a Texture with the given texture URL and tiling
+fn texture_material(texture: &str, decal_size: u64, studs_per_u: f64, studs_per_v: f64, offset_u: f64, offset_v: f64) -> Material {
+    if let Some(id) = asset_id(texture) {
+        Material::Texture { id, size_x: decal_size, size_y: decal_size, studs_per_u, studs_per_v, offset_u, offset_v }
+    } else {
+        Material::Custom { texture: "decal", fill: false, generate: true, size_x: 32, size_y: 32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a tree from (class name, name, parent) entries, in insertion order
+    fn build_tree(instances: &[(&str, &str, Option<usize>)]) -> InstanceTree {
+        let mut tree = InstanceTree::new();
+        for (index, (class_name, name, parent)) in instances.iter().enumerate() {
+            let mut instance = Instance::new(class_name.to_string(), format!("RBX{}", index));
+            instance.name = name.to_string();
+            tree.insert(instance, *parent);
+        }
+        tree
+    }
+
+    /// Workspace with a map, a lamp inside the map, and a character; ServerStorage with a lobby map
+    fn place() -> InstanceTree {
+        build_tree(&[
+            ("Workspace", "Workspace", None),       // 0
+            ("Model", "Map", Some(0)),              // 1
+            ("Part", "Floor", Some(1)),             // 2
+            ("Model", "Lamp", Some(1)),             // 3
+            ("Part", "Bulb", Some(3)),              // 4
+            ("Model", "Player", Some(0)),           // 5
+            ("Humanoid", "Humanoid", Some(5)),      // 6
+            ("ServerStorage", "ServerStorage", None),   // 7
+            ("Folder", "Maps", Some(7)),            // 8
+            ("Model", "Lobby", Some(8)),            // 9
+            ("Part", "LobbyFloor", Some(9)),        // 10
+        ])
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        let matches = |pattern: &str, text: &str| Glob::new(pattern).matches(text);
+        assert!(matches("Lamp", "Lamp"));
+        assert!(!matches("Lamp", "Lamps"));
+        assert!(matches("Lamp?", "Lamps"));
+        assert!(matches("?amp", "Lamp"));
+        assert!(matches("*", ""));
+        assert!(matches("Spawn*", "SpawnLocation"));
+        assert!(matches("*Light*", "StreetLightPole"));
+        assert!(matches("Wür*", "Würfel"));
+        assert!(matches("W?rfel", "Würfel"));
+        assert!(!matches("Debug*", "NoDebug"));
+    }
+
+    #[test]
+    fn glob_paths() {
+        let matches = |pattern: &str, text: &str| Glob::new(pattern).matches(text);
+        assert!(matches("Workspace/*", "Workspace/Map"));
+        assert!(!matches("Workspace/*", "Workspace/Map/Floor"));
+        assert!(!matches("Workspace/?", "Workspace/A/B"));
+        assert!(matches("Workspace/Map/**", "Workspace/Map/Floor"));
+        assert!(matches("Workspace/Map/**", "Workspace/Map/Lamp/Bulb"));
+        assert!(!matches("Workspace/Map/**", "Workspace/Map"));
+        assert!(!matches("Workspace/Map/**", "Workspace/Mapper/Floor"));
+        assert!(matches("**/Bulb", "Bulb"));
+        assert!(matches("**/Bulb", "Workspace/Map/Lamp/Bulb"));
+        assert!(matches("Workspace/**/Bulb", "Workspace/Bulb"));
+        assert!(matches("Workspace/**/Bulb", "Workspace/Map/Lamp/Bulb"));
+        assert!(!matches("Workspace/**/Bulb", "Workspace/Map/LampBulb"));
+        assert!(matches("**Bulb", "Workspace/Map/LampBulb"));
+    }
+
+    #[test]
+    fn glob_backtracking_is_bounded() {
+        // Without memoization, every '*' multiplies the number of ways to split the text
+        let text = format!("{}/{}", "a".repeat(200), "b".repeat(200));
+        assert!(!Glob::new("**/**/*a*a*a*a*a*a*a*a*a*a*c").matches(&text));
+        assert!(Glob::new("**/**/*b*b*b*b*b*b*b*b*b*b").matches(&text));
+    }
+
+    #[test]
+    fn exclude_filters() {
+        let tree = place();
+        let mut filters = InstanceFilters {
+            include: Vec::new(),
+            exclude: vec![InstanceFilter::new(FilterKind::Path, "Workspace/Map/Lamp"), InstanceFilter::new(FilterKind::Class, "Humanoid"), InstanceFilter::new(FilterKind::Name, "Floo?")],
+            include_removed: 0,
+        };
+        let filtered = filters.apply(&tree, &[0]);
+        assert_eq!(filtered, HashSet::from([3, 6, 2]));
+        assert_eq!(filters.exclude.iter().map(|filter| filter.removed).collect::<Vec<_>>(), [2, 1, 1]);
+    }
+
+    #[test]
+    fn include_filters() {
+        let tree = place();
+        let mut filters = InstanceFilters {
+            include: vec![InstanceFilter::new(FilterKind::Path, "Workspace/Map/**")],
+            exclude: vec![InstanceFilter::new(FilterKind::Name, "Bulb")],
+            include_removed: 0,
+        };
+        // Ancestors of included instances are kept, everything else is removed
+        let filtered = filters.apply(&tree, &[0]);
+        assert_eq!(filtered, HashSet::from([4, 5, 6]));
+        assert_eq!(filters.include_removed, 2);
+        assert_eq!(filters.exclude[0].removed, 1);
+
+        // Instances outside of all include filters are counted once
+        let mut filters = InstanceFilters {
+            include: vec![InstanceFilter::new(FilterKind::Name, "Lamp"), InstanceFilter::new(FilterKind::Class, "Humanoid")],
+            exclude: Vec::new(),
+            include_removed: 0,
+        };
+        let filtered = filters.apply(&tree, &[0, 9]);
+        assert_eq!(filtered, HashSet::from([2, 9, 10]));
+        assert_eq!(filters.include_removed, 3);
+        assert_eq!(filters.include_removed, filtered.len());
+    }
+
+    #[test]
+    fn filters_match_full_paths_of_included_roots() {
+        let tree = place();
+        let mut filters = InstanceFilters {
+            include: Vec::new(),
+            exclude: vec![InstanceFilter::new(FilterKind::Path, "ServerStorage/Maps/Lobby/*")],
+            include_removed: 0,
+        };
+        assert_eq!(filters.apply(&tree, &[9]), HashSet::from([10]));
+    }
+
+    #[test]
+    fn included_roots() {
+        let tree = place();
+        assert_eq!(find_instances(&tree, "ServerStorage/Maps/Lobby"), [9]);
+        assert_eq!(find_instances(&tree, "/Workspace/Map/"), [1]);
+        assert!(find_instances(&tree, "Workspace/Lobby").is_empty());
+
+        assert_eq!(traversal_roots(&tree, &[]), [0]);
+        // Instances inside of the Workspace are already read
+        assert_eq!(traversal_roots(&tree, &[3, 9, 8]), [0, 8]);
+
+        // Files without a Workspace are read from every root
+        let model = build_tree(&[("Model", "House", None), ("Part", "Wall", Some(0)), ("Part", "Door", None)]);
+        assert_eq!(traversal_roots(&model, &[]), [0, 2]);
+    }
+
+    /// Part with a Decal and a Texture on its front face
+    fn decorated_part() -> InstanceTree {
+        let mut tree = build_tree(&[
+            ("Workspace", "Workspace", None),   // 0
+            ("Part", "Sign", Some(0)),          // 1
+            ("Decal", "Poster", Some(1)),       // 2
+            ("Texture", "Bricks", Some(1)),     // 3
+        ]);
+        let properties = [
+            ("size", Property::Vector3(Vector3 { x: 4.0, y: 4.0, z: 1.0 })),
+            ("CFrame", Property::CoordinateFrame(CFrame { position: Vector3 { x: 0.0, y: 2.0, z: 0.0 }, rot_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] })),
+            ("Color3uint8", Property::Color3uint8(Color3::white())),
+            ("Transparency", Property::Float(0.0)),
+            ("Reflectance", Property::Float(0.0)),
+            ("Material", Property::Token(256)),
+        ];
+        for (name, value) in properties {
+            tree.get_mut(1).properties.insert(name.to_string(), value);
+        }
+        for child in [2, 3] {
+            tree.get_mut(child).properties.insert("Face".to_string(), Property::Token(DECAL_FRONT as u32));
+            tree.get_mut(child).properties.insert("Texture".to_string(), Property::Content("rbxassetid://1".to_string()));
+        }
+        for name in ["StudsPerTileU", "StudsPerTileV", "OffsetStudsU", "OffsetStudsV"] {
+            tree.get_mut(3).properties.insert(name.to_string(), Property::Float(2.0));
         }
+        tree
+    }
+
+    #[test]
+    fn filtered_decals_are_skipped() {
+        let tree = decorated_part();
+        let parse = |exclude: InstanceFilter| {
+            let mut filters = InstanceFilters { include: Vec::new(), exclude: vec![exclude], include_removed: 0 };
+            let filtered = filters.apply(&tree, &[0]);
+            let settings = PartSettings { decal_size: 256, material_variants: &[], include_tools: false, include_characters: false, filtered: &filtered };
+            let mut parts = Vec::new();
+            parse_parts(&tree, 0, &mut parts, &mut Vec::new(), None, false, &settings);
+            assert_eq!(parts.len(), 1);
+            (parts[0].overlays, parts[0].decals)
+        };
+
+        let (overlays, decals) = parse(InstanceFilter::new(FilterKind::Name, "Unused"));
+        assert!(overlays[DECAL_FRONT].is_some());
+        assert!(decals[DECAL_FRONT].is_some());
+
+        let (overlays, decals) = parse(InstanceFilter::new(FilterKind::Class, "Decal"));
+        assert_eq!(overlays, [None; 6]);
+        assert!(decals[DECAL_FRONT].is_some());
+
+        let (overlays, decals) = parse(InstanceFilter::new(FilterKind::Path, "Workspace/Sign/Bricks"));
+        assert!(overlays[DECAL_FRONT].is_some());
+        assert_eq!(decals[DECAL_FRONT], None);
+    }
+
+    #[test]
+    fn lights_in_attachments() {
+        let mut tree = build_tree(&[
+            ("Workspace", "Workspace", None),       // 0
+            ("Part", "Lamp", Some(0)),              // 1
+            ("Attachment", "Socket", Some(1)),      // 2
+            ("PointLight", "Bulb", Some(2)),        // 3
+            ("PointLight", "Glow", Some(1)),        // 4
+        ]);
+        let offset = CFrame { position: Vector3 { x: 0.0, y: 1.0, z: 0.0 }, rot_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] };
+        tree.get_mut(2).properties.insert("CFrame".to_string(), Property::CoordinateFrame(offset));
+        for light in [3, 4] {
+            tree.get_mut(light).properties.insert("Color".to_string(), Property::Color3(Color3::white()));
+            tree.get_mut(light).properties.insert("Brightness".to_string(), Property::Float(1.0));
+            tree.get_mut(light).properties.insert("Range".to_string(), Property::Float(8.0));
+        }
+
+        let mut lights = Vec::new();
+        parse_lights(&tree, 0, &mut lights, &HashSet::new());
+        assert_eq!(lights.iter().map(|light| (light.part, light.attachment)).collect::<Vec<_>>(), [("RBX1", Some(offset)), ("RBX1", None)]);
+    }
+
+    #[test]
+    fn clock_times() {
+        assert_eq!(time_of_day("14:00:00"), Some(14.0));
+        assert_eq!(time_of_day("06:30:00"), Some(6.5));
+        assert_eq!(time_of_day("23:59:36"), Some(23.0 + 59.0 / 60.0 + 36.0 / 3600.0));
+        assert_eq!(time_of_day("8"), Some(8.0));
+        assert_eq!(time_of_day("noon"), None);
     }
 }
@@ -15,6 +15,7 @@ pub struct RobloxTexture {
     pub reflectance: u8,
     pub scale: TextureScale,
     pub no_offset: bool,
+    pub self_illuminated: bool,
     pub dimension_x: u64,
     pub dimension_y: u64,
 }
@@ -33,25 +34,29 @@ impl VMFTexture for RobloxTexture {
         if let Material::Custom { texture, generate: false , ..} = self.material {
             format!("{}", texture)
         } else {
-            format!("rbx/{}_{:x}-{:x}-{:x}-{:x}-{:x}", self.material, self.color.red, self.color.blue, self.color.green, self.transparency, self.reflectance)
+            format!(
+                "rbx/{}_{:x}-{:x}-{:x}-{:x}-{:x}{}",
+                self.material, self.color.red, self.color.blue, self.color.green, self.transparency, self.reflectance,
+                if self.self_illuminated { "_selfillum" } else { "" }
+            )
         }
     }
 
-    fn scale_x(&self, side: Side) -> f64 {
+    fn scale_x(&self, side: &Side) -> f64 {
         match self.scale {
             TextureScale::FILL => (Vector3::from_array(side.plane[2]) - Vector3::from_array(side.plane[1])).magnitude() / (self.dimension_x as f64),
             TextureScale::FIXED { scale_x, .. } => scale_x
         }
     }
 
-    fn scale_z(&self, side: Side) -> f64 {
+    fn scale_z(&self, side: &Side) -> f64 {
         match self.scale {
             TextureScale::FILL => (Vector3::from_array(side.plane[2]) - Vector3::from_array(side.plane[0])).magnitude() / (self.dimension_y as f64),
             TextureScale::FIXED { scale_z, .. } => scale_z
         }
     }
 
-    fn offset_x(&self, side: Side) -> f64 {
+    fn offset_x(&self, side: &Side) -> f64 {
         if self.no_offset {
             0.0
         } else {
@@ -61,13 +66,19 @@ impl VMFTexture for RobloxTexture {
                 TextureFace::Z_POS => -side.plane[2][0],
                 TextureFace::Z_NEG => side.plane[2][0],
                 TextureFace::Y_POS => -side.plane[2][1],
-                TextureFace::Y_NEG => side.plane[2][1]
+                TextureFace::Y_NEG => side.plane[2][1],
+                TextureFace::ALIGNED { u_axis, origin, .. } => -Vector3::from_array(origin).dot(Vector3::from_array(u_axis)),
             };
-            (position / self.scale_x(side)) % (self.dimension_x as f64)
+            // Textures are shifted by their offset, converted from studs to texels
+            let offset = match self.material {
+                Material::Texture { studs_per_u, offset_u, .. } if studs_per_u > 0.0 => offset_u / studs_per_u * (self.dimension_x as f64),
+                _ => 0.0
+            };
+            (position / self.scale_x(side) - offset) % (self.dimension_x as f64)
         }
     }
 
-    fn offset_y(&self, side: Side) -> f64 {
+    fn offset_y(&self, side: &Side) -> f64 {
         if self.no_offset {
             0.0
         } else {
@@ -77,9 +88,14 @@ impl VMFTexture for RobloxTexture {
                 TextureFace::Z_POS => side.plane[2][2],
                 TextureFace::Z_NEG => -side.plane[2][2],
                 TextureFace::Y_POS => -side.plane[2][0],
-                TextureFace::Y_NEG => -side.plane[2][0]
+                TextureFace::Y_NEG => -side.plane[2][0],
+                TextureFace::ALIGNED { v_axis, origin, .. } => -Vector3::from_array(origin).dot(Vector3::from_array(v_axis)),
+            };
+            let offset = match self.material {
+                Material::Texture { studs_per_v, offset_v, .. } if studs_per_v > 0.0 => offset_v / studs_per_v * (self.dimension_y as f64),
+                _ => 0.0
             };
-            (position / self.scale_z(side)) % (self.dimension_y as f64)
+            (position / self.scale_z(side) - offset) % (self.dimension_y as f64)
         }
     }
 }
\ No newline at end of file
@@ -2,5 +2,6 @@
 
 pub mod rbx;
 pub mod vmf;
+pub mod vtf;
 pub mod conv;
 
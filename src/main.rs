@@ -8,16 +8,17 @@ use std::path::Path;
 use std::process::ExitCode;
 use clap::{Arg, ArgAction, Command};
 use clap::builder::OsStringValueParser;
-use crate::conv::{ConvertOptions, OwnedOrMut, OwnedOrRef};
+use crate::conv::{ColorMode, ConvertOptions, Game, OwnedOrMut, OwnedOrRef, SphereMode, SurfaceLightMode, TrussMode, MAX_CYLINDER_SIDES, MAX_SPHERE_SEGMENTS, MIN_SPHERE_SEGMENTS};
 
 mod rbx;
 mod vmf;
+mod vtf;
 mod conv;
 
 fn main() -> ExitCode {
     let matches = Command::new("RBXLX2VMF")
         .version("1.0")
-        .about("Converts Roblox RBXLX files to Valve VMF files.")
+        .about("Converts Roblox RBXLX/RBXL files to Valve VMF files.")
         .arg(Arg::new("input")
             .long("input")
             .short('i')
@@ -43,6 +44,13 @@ fn main() -> ExitCode {
             .required(false)
             .num_args(1)
             .value_parser(OsStringValueParser::new()))
+        .arg(Arg::new("asset-dir")
+            .long("asset-dir")
+            .value_name("FOLDER")
+            .help("Sets folder of local Roblox assets, stored as <asset id>.png or <asset id>.jpg")
+            .required(false)
+            .num_args(1)
+            .value_parser(OsStringValueParser::new()))
         .arg(Arg::new("no-textures")
             .long("no-textures")
             .help("disables texture generation")
@@ -51,6 +59,30 @@ fn main() -> ExitCode {
             .long("dev-textures")
             .help("use developer textures instead of roblox textures")
             .action(ArgAction::SetTrue))
+        .arg(Arg::new("color-mode")
+            .long("color-mode")
+            .help("sets how part colors are applied to textures")
+            .value_parser(["tinted", "baked"])
+            .required(false)
+            .default_value("tinted")
+            .num_args(1))
+        .arg(Arg::new("color-tolerance")
+            .long("color-tolerance")
+            .help("sets how far apart (per channel, 0-255) baked colors must be to get separate textures")
+            .value_parser(|input: &str| input.parse::<u8>())
+            .required(false)
+            .default_value("8")
+            .num_args(1))
+        .arg(Arg::new("surfaceprop")
+            .long("surfaceprop")
+            .value_name("MATERIAL=SURFACEPROP")
+            .help("overrides the $surfaceprop of a material, by texture name (E.g. plastic=metal), may be repeated")
+            .value_parser(|input: &str| match input.split_once('=') {
+                Some((material, surface_prop)) if !material.is_empty() && !surface_prop.is_empty() => Ok((material.to_string(), surface_prop.to_string())),
+                _ => Err("expected <material>=<surfaceprop>".to_string())
+            })
+            .action(ArgAction::Append)
+            .required(false))
         .arg(Arg::new("auto-skybox")
             .long("auto-skybox")
             .help("enables automatic skybox (Warning: Results in highly unoptimized map)")
@@ -74,11 +106,119 @@ fn main() -> ExitCode {
             .num_args(1))
         .arg(Arg::new("decal-size")
             .long("decal-size")
-            .help("sets downloaded decal texture size")
-            .value_parser(|input: &str| input.parse::<u64>())
+            .help("sets decal texture size")
+            .value_parser(|input: &str| match input.parse::<u64>() {
+                Ok(size) if !size.is_power_of_two() || size > 4096 => Err("decal size must be a power of two, up to 4096".to_string()),
+                result => result.map_err(|error| error.to_string())
+            })
             .required(false)
             .default_value("256")
             .num_args(1))
+        .arg(Arg::new("cylinder-sides")
+            .long("cylinder-sides")
+            .help("sets the number of sides of cylinder brushes")
+            .value_parser(|input: &str| match input.parse::<u32>() {
+                Ok(sides) if !(3..=MAX_CYLINDER_SIDES).contains(&sides) => Err(format!("cylinders must have 3 to {} sides", MAX_CYLINDER_SIDES)),
+                result => result.map_err(|error| error.to_string())
+            })
+            .required(false)
+            .default_value("12")
+            .num_args(1))
+        .arg(Arg::new("sphere-mode")
+            .long("sphere-mode")
+            .help("sets how spherical parts are converted, see --displacement-power and --sphere-segments")
+            .value_parser(["displacement", "polyhedron", "skip"])
+            .required(false)
+            .default_value("displacement")
+            .num_args(1))
+        .arg(Arg::new("displacement-power")
+            .long("displacement-power")
+            .help("sets the power of sphere displacements")
+            .value_parser(|input: &str| match input.parse::<u32>() {
+                Ok(power) if !(2..=4).contains(&power) => Err("displacement power must be 2, 3 or 4".to_string()),
+                result => result.map_err(|error| error.to_string())
+            })
+            .required(false)
+            .default_value("2")
+            .num_args(1))
+        .arg(Arg::new("sphere-segments")
+            .long("sphere-segments")
+            .help("sets the number of segments around polyhedron spheres")
+            .value_parser(|input: &str| match input.parse::<u32>() {
+                Ok(segments) if !(MIN_SPHERE_SEGMENTS..=MAX_SPHERE_SEGMENTS).contains(&segments) => Err(format!("spheres must have {} to {} segments", MIN_SPHERE_SEGMENTS, MAX_SPHERE_SEGMENTS)),
+                result => result.map_err(|error| error.to_string())
+            })
+            .required(false)
+            .default_value("12")
+            .num_args(1))
+        .arg(Arg::new("truss-mode")
+            .long("truss-mode")
+            .help("sets how truss parts are converted")
+            .value_parser(["lattice", "textured"])
+            .required(false)
+            .default_value("lattice")
+            .num_args(1))
+        .arg(Arg::new("surface-lights")
+            .long("surface-lights")
+            .help("sets how SurfaceLights are converted")
+            .value_parser(["lights", "selfillum"])
+            .required(false)
+            .default_value("lights")
+            .num_args(1))
+        .arg(Arg::new("hinge-knobs")
+            .long("hinge-knobs")
+            .help("adds knob brushes to Hinge and Motor surfaces")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("include")
+            .long("include")
+            .value_name("PATH")
+            .help("also converts parts of a service or instance, by path of names (E.g. ReplicatedStorage, ServerStorage/Lobby), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("include-tools")
+            .long("include-tools")
+            .help("converts parts of Tools and Accessories")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("include-characters")
+            .long("include-characters")
+            .help("converts parts of character models (Models with a Humanoid)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("include-path")
+            .long("include-path")
+            .value_name("GLOB")
+            .help("only converts instances by full path and their descendants, '*' matches within a name and '**' across names (E.g. Workspace/Map/**), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("include-class")
+            .long("include-class")
+            .value_name("CLASS")
+            .help("only converts instances of a class and their descendants (E.g. Model), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("include-name")
+            .long("include-name")
+            .value_name("PATTERN")
+            .help("only converts instances by name and their descendants, '*' matches any characters (E.g. Lobby*), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("exclude-path")
+            .long("exclude-path")
+            .value_name("GLOB")
+            .help("leaves out instances by full path, '*' matches within a name and '**' across names (E.g. Workspace/Map/**), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("exclude-class")
+            .long("exclude-class")
+            .value_name("CLASS")
+            .help("leaves out instances of a class (E.g. Model), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("exclude-name")
+            .long("exclude-name")
+            .value_name("PATTERN")
+            .help("leaves out instances by name, '*' matches any characters (E.g. Debug*), may be repeated")
+            .action(ArgAction::Append)
+            .required(false))
         .arg(Arg::new("game")
             .long("game")
             .short('g')
@@ -94,21 +234,53 @@ fn main() -> ExitCode {
             input_name: &matches.get_one::<OsString>("input").unwrap().as_os_str().to_string_lossy(),
             input_path: matches.get_one::<OsString>("input").unwrap(),
             output_path: matches.get_one::<OsString>("output").unwrap(),
+            asset_folder: matches.get_one::<OsString>("asset-dir").map(OsString::as_os_str),
             texture_output_folder: {
                 let texture_folder = matches.get_one::<OsString>("texture-output").unwrap();
-                if let Err(error) = std::fs::create_dir_all(Path::new(texture_folder).join("rbx")) {
-                    println!("error: could not create texture output directory {}", error);
-                    std::process::exit(-1)
+                for directory in ["rbx", "skybox"] {
+                    if let Err(error) = std::fs::create_dir_all(Path::new(texture_folder).join(directory)) {
+                        println!("error: could not create texture output directory {}", error);
+                        std::process::exit(-1)
+                    }
                 }
                 texture_folder
             },
             is_texture_output_enabled: !matches.get_one("no-textures").unwrap_or(&false),
             use_developer_textures: *matches.get_one("dev-textures").unwrap_or(&false),
+            color_mode: match matches.get_one::<String>("color-mode").unwrap().as_str() {
+                "baked" => ColorMode::Baked { tolerance: *matches.get_one("color-tolerance").unwrap() },
+                _ => ColorMode::Tinted
+            },
+            surface_prop_overrides: matches.get_many::<(String, String)>("surfaceprop").unwrap_or_default().cloned().collect(),
             map_scale: *matches.get_one("map-scale").unwrap(),
             auto_skybox_enabled: *matches.get_one("auto-skybox").unwrap_or(&false),
             skybox_clearance: *matches.get_one("skybox-height").unwrap_or(&0f64),
             optimization_enabled: *matches.get_one("optimize").unwrap_or(&false),
             decal_size: *matches.get_one("decal-size").unwrap(),
+            cylinder_sides: *matches.get_one("cylinder-sides").unwrap(),
+            sphere_mode: match matches.get_one::<String>("sphere-mode").unwrap().as_str() {
+                "polyhedron" => SphereMode::Polyhedron { segments: *matches.get_one("sphere-segments").unwrap() },
+                "skip" => SphereMode::Skip,
+                _ => SphereMode::Displacement { power: *matches.get_one("displacement-power").unwrap() }
+            },
+            truss_mode: match matches.get_one::<String>("truss-mode").unwrap().as_str() {
+                "textured" => TrussMode::Textured,
+                _ => TrussMode::Lattice
+            },
+            surface_light_mode: match matches.get_one::<String>("surface-lights").unwrap().as_str() {
+                "selfillum" => SurfaceLightMode::SelfIllum,
+                _ => SurfaceLightMode::Lights
+            },
+            hinge_knobs_enabled: *matches.get_one("hinge-knobs").unwrap_or(&false),
+            included_paths: matches.get_many::<String>("include").unwrap_or_default().cloned().collect(),
+            include_tools: *matches.get_one("include-tools").unwrap_or(&false),
+            include_characters: *matches.get_one("include-characters").unwrap_or(&false),
+            included_path_patterns: matches.get_many::<String>("include-path").unwrap_or_default().cloned().collect(),
+            included_classes: matches.get_many::<String>("include-class").unwrap_or_default().cloned().collect(),
+            included_names: matches.get_many::<String>("include-name").unwrap_or_default().cloned().collect(),
+            excluded_paths: matches.get_many::<String>("exclude-path").unwrap_or_default().cloned().collect(),
+            excluded_classes: matches.get_many::<String>("exclude-class").unwrap_or_default().cloned().collect(),
+            excluded_names: matches.get_many::<String>("exclude-name").unwrap_or_default().cloned().collect(),
             skybox_name: match matches.get_one::<String>("game").unwrap().as_str() {
                 "css" => "sky_day01_05",
                 "csgo" => "sky_day02_05",
@@ -124,7 +296,8 @@ fn main() -> ExitCode {
                 "portal" => "sky_day01_05_hdr",
                 "tf2" => "sky_day01_01",
                 _ => "default_skybox_fixme"
-            }
+            },
+            game: Game::from_id(matches.get_one::<String>("game").unwrap()).unwrap()
         })
     );
 
@@ -143,14 +316,32 @@ struct CLIConvertOptions<'a> {
     input_path: &'a OsStr,
     output_path: &'a OsStr,
     texture_output_folder: &'a OsStr,
+    asset_folder: Option<&'a OsStr>,
     is_texture_output_enabled: bool,
     use_developer_textures: bool,
+    color_mode: ColorMode,
+    surface_prop_overrides: Vec<(String, String)>,
     map_scale: f64,
     auto_skybox_enabled: bool,
     skybox_clearance: f64,
     optimization_enabled: bool,
     decal_size: u64,
-    skybox_name: &'a str
+    cylinder_sides: u32,
+    sphere_mode: SphereMode,
+    truss_mode: TrussMode,
+    surface_light_mode: SurfaceLightMode,
+    hinge_knobs_enabled: bool,
+    included_paths: Vec<String>,
+    include_tools: bool,
+    include_characters: bool,
+    included_path_patterns: Vec<String>,
+    included_classes: Vec<String>,
+    included_names: Vec<String>,
+    excluded_paths: Vec<String>,
+    excluded_classes: Vec<String>,
+    excluded_names: Vec<String>,
+    skybox_name: &'a str,
+    game: Game
 }
 
 impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
@@ -165,7 +356,7 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
         &self.input_name
     }
 
-    fn read_input_data(&self) ->  OwnedOrRef<'_, String> {
+    fn read_input_data(&self) ->  OwnedOrRef<'_, Vec<u8>> {
         let mut file = match File::open(self.input_path) {
             Ok(file) => file,
             Err(error) => {
@@ -173,8 +364,8 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
                 std::process::exit(-1)
             }
         };
-        let mut buffer = String::with_capacity(file.metadata().as_ref().map(Metadata::len).unwrap_or(0) as usize);
-        match file.read_to_string(&mut buffer) {
+        let mut buffer = Vec::with_capacity(file.metadata().as_ref().map(Metadata::len).unwrap_or(0) as usize);
+        match file.read_to_end(&mut buffer) {
             Ok(_) => {}
             Err(error) => {
                 println!("error: Could not read input {}", error);
@@ -205,6 +396,17 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
         }
     }
 
+    fn read_asset(&self, id: u64) -> Option<Vec<u8>> {
+        // Downloaded assets are stored by id, as PNG or JPEG images
+        let asset_folder = Path::new(self.asset_folder?);
+        [format!("{}.png", id), format!("{}.jpg", id)].iter()
+            .find_map(|file_name| std::fs::read(asset_folder.join(file_name)).ok())
+    }
+
+    fn has_local_assets(&self) -> bool {
+        self.asset_folder.is_some()
+    }
+
     fn texture_output_enabled(&self) -> bool {
         self.is_texture_output_enabled
     }
@@ -213,6 +415,14 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
         self.use_developer_textures
     }
 
+    fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    fn surface_prop_overrides(&self) -> &[(String, String)] {
+        &self.surface_prop_overrides
+    }
+
     fn map_scale(&self) -> f64 {
         self.map_scale
     }
@@ -229,6 +439,62 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
         self.optimization_enabled
     }
 
+    fn cylinder_sides(&self) -> u32 {
+        self.cylinder_sides
+    }
+
+    fn sphere_mode(&self) -> SphereMode {
+        self.sphere_mode
+    }
+
+    fn truss_mode(&self) -> TrussMode {
+        self.truss_mode
+    }
+
+    fn surface_light_mode(&self) -> SurfaceLightMode {
+        self.surface_light_mode
+    }
+
+    fn hinge_knobs_enabled(&self) -> bool {
+        self.hinge_knobs_enabled
+    }
+
+    fn included_paths(&self) -> &[String] {
+        &self.included_paths
+    }
+
+    fn include_tools(&self) -> bool {
+        self.include_tools
+    }
+
+    fn include_characters(&self) -> bool {
+        self.include_characters
+    }
+
+    fn included_path_patterns(&self) -> &[String] {
+        &self.included_path_patterns
+    }
+
+    fn included_classes(&self) -> &[String] {
+        &self.included_classes
+    }
+
+    fn included_names(&self) -> &[String] {
+        &self.included_names
+    }
+
+    fn excluded_paths(&self) -> &[String] {
+        &self.excluded_paths
+    }
+
+    fn excluded_classes(&self) -> &[String] {
+        &self.excluded_classes
+    }
+
+    fn excluded_names(&self) -> &[String] {
+        &self.excluded_names
+    }
+
     fn decal_size(&self) -> u64 {
         self.decal_size
     }
@@ -236,4 +502,8 @@ impl<'a> ConvertOptions<File> for CLIConvertOptions<'a> {
     fn skybox_name(&self) -> &str {
         self.skybox_name
     }
+
+    fn game(&self) -> Game {
+        self.game
+    }
 }
\ No newline at end of file
@@ -16,11 +16,14 @@ pub mod textures {
     pub const FORCEFIELD: &'static [u8] = include_bytes!("../textures/forcefield.vtf");
     pub const GLASS: &'static [u8] = include_bytes!("../textures/glass.vtf");
     pub const GRANITE: &'static [u8] = include_bytes!("../textures/granite.vtf");
+    pub const GLUE: &'static [u8] = include_bytes!("../textures/glue.vtf");
     pub const GRASS: &'static [u8] = include_bytes!("../textures/grass.vtf");
+    pub const HINGE: &'static [u8] = include_bytes!("../textures/hinge.vtf");
     pub const ICE: &'static [u8] = include_bytes!("../textures/ice.vtf");
     pub const INLET: &'static [u8] = include_bytes!("../textures/inlet.vtf");
     pub const MARBLE: &'static [u8] = include_bytes!("../textures/marble.vtf");
     pub const METAL: &'static [u8] = include_bytes!("../textures/metal.vtf");
+    pub const MOTOR: &'static [u8] = include_bytes!("../textures/motor.vtf");
     pub const PEBBLE: &'static [u8] = include_bytes!("../textures/pebble.vtf");
     pub const PLASTIC: &'static [u8] = include_bytes!("../textures/plastic.vtf");
     pub const RUST: &'static [u8] = include_bytes!("../textures/rust.vtf");
@@ -28,7 +31,11 @@ pub mod textures {
     pub const SLATE: &'static [u8] = include_bytes!("../textures/slate.vtf");
     pub const SMOOTHPLASTIC: &'static [u8] = include_bytes!("../textures/smoothplastic.vtf");
     pub const SPAWNLOCATION: &'static [u8] = include_bytes!("../textures/spawnlocation.vtf");
+    pub const STEPPINGMOTOR: &'static [u8] = include_bytes!("../textures/steppingmotor.vtf");
     pub const STUDS: &'static [u8] = include_bytes!("../textures/studs.vtf");
+    pub const TRUSS: &'static [u8] = include_bytes!("../textures/truss.vtf");
+    pub const UNIVERSAL: &'static [u8] = include_bytes!("../textures/universal.vtf");
+    pub const WELD: &'static [u8] = include_bytes!("../textures/weld.vtf");
     pub const WOOD: &'static [u8] = include_bytes!("../textures/wood.vtf");
     pub const WOODPLANKS: &'static [u8] = include_bytes!("../textures/woodplanks.vtf");
 }
@@ -95,12 +102,190 @@ impl<'a> IntoIterator for &'a Model<'a> {
     }
 }
 
+/// Roblox property value; Covers the value types found in RBXLX files
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+    CoordinateFrame(CFrame),
+    Vector3(Vector3),
+    Color3uint8(Color3),
+    /// Floating point colour, rounded to 8 bits per channel
+    Color3(Color3),
+    Token(u32),
+    Content(String),
+    BinaryString(String),
+    SharedString(String),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+/// Struct to represent a Roblox instance, without any class-specific interpretation of its properties
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub class_name: String,
+    pub referent: String,
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub properties: HashMap<String, Property>,
+}
+
+impl Instance {
+    pub fn new(class_name: String, referent: String) -> Instance {
+        Instance {
+            class_name,
+            referent,
+            name: String::new(),
+            parent: None,
+            children: Vec::new(),
+            properties: HashMap::new(),
+        }
+    }
+
+    pub fn get_cframe(&self, name: &str) -> Option<CFrame> {
+        match self.properties.get(name)? {
+            Property::CoordinateFrame(cframe) => Some(*cframe),
+            _ => None
+        }
+    }
+
+    pub fn get_vector3(&self, name: &str) -> Option<Vector3> {
+        match self.properties.get(name)? {
+            Property::Vector3(vector) => Some(*vector),
+            _ => None
+        }
+    }
+
+    pub fn get_color3uint8(&self, name: &str) -> Option<Color3> {
+        match self.properties.get(name)? {
+            Property::Color3uint8(color) => Some(*color),
+            _ => None
+        }
+    }
+
+    pub fn get_color3(&self, name: &str) -> Option<Color3> {
+        match self.properties.get(name)? {
+            Property::Color3(color) => Some(*color),
+            _ => None
+        }
+    }
+
+    pub fn get_token(&self, name: &str) -> Option<u32> {
+        match self.properties.get(name)? {
+            Property::Token(token) => Some(*token),
+            _ => None
+        }
+    }
+
+    /// Returns a Content property; The binary format stores these as strings, so those are accepted as well
+    pub fn get_content(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name)? {
+            Property::Content(content) | Property::String(content) => Some(content),
+            _ => None
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.properties.get(name)? {
+            Property::Float(float) => Some(*float),
+            _ => None
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.properties.get(name)? {
+            Property::Int(int) => Some(*int),
+            _ => None
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.properties.get(name)? {
+            Property::Bool(bool) => Some(*bool),
+            _ => None
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name)? {
+            Property::String(string) => Some(string),
+            _ => None
+        }
+    }
+}
+
+/// Tree of Roblox instances, as read from either the XML or binary file format
+#[derive(Debug, Default)]
+pub struct InstanceTree {
+    instances: Vec<Instance>,
+}
+
+impl InstanceTree {
+    pub fn new() -> InstanceTree {
+        InstanceTree {
+            instances: Vec::new(),
+        }
+    }
+
+    /// Adds an instance to the tree, returns the index of the new instance
+    pub fn insert(&mut self, instance: Instance, parent: Option<usize>) -> usize {
+        let index = self.instances.len();
+        self.instances.push(instance);
+        self.set_parent(index, parent);
+        index
+    }
+
+    /// Sets the parent of an instance; Only valid for instances that do not have a parent yet
+    pub fn set_parent(&mut self, index: usize, parent: Option<usize>) {
+        debug_assert!(self.instances[index].parent.is_none());
+        self.instances[index].parent = parent;
+        if let Some(parent) = parent {
+            self.instances[parent].children.push(index);
+        }
+    }
+
+    /// Returns true if `ancestor` is the instance at `index` or one of its ancestors
+    pub fn is_ancestor(&self, ancestor: usize, mut index: usize) -> bool {
+        loop {
+            if index == ancestor {
+                return true;
+            }
+            match self.instances[index].parent {
+                Some(parent) => index = parent,
+                None => return false
+            }
+        }
+    }
+
+    pub fn get(&self, index: usize) -> &Instance {
+        &self.instances[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Instance {
+        &mut self.instances[index]
+    }
+
+    /// Returns the indices of all instances without a parent
+    pub fn roots(&self) -> impl Iterator<Item=usize> + '_ {
+        (0..self.instances.len()).filter(|index| self.instances[*index].parent.is_none())
+    }
+
+    pub fn children(&self, index: usize) -> impl Iterator<Item=&Instance> {
+        self.instances[index].children.iter().map(|child| &self.instances[*child])
+    }
+}
+
 /// Struct to represent Roblox parts
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Part<'a> {
     pub part_type: PartType,
     pub shape: PartShape,
     pub is_detail: bool,
+    /// Part emits light from its surface, through `$selfillum`
+    pub is_self_illuminated: bool,
+    /// Visgroup of the innermost Model or Folder containing this part
+    pub visgroup: Option<u32>,
     pub referent: &'a str,
     pub size: Vector3,
     pub cframe: CFrame,
@@ -108,13 +293,75 @@ pub struct Part<'a> {
     pub transparency: f64,
     pub reflectance: f64,
     pub material: Material,
-    pub decals: [Option<Material>; 6],   // 0 = Front =-Z, 1 = Back = +Z, 2 = Top = +Y, 3 Bottom = -Y, 4 Right = +X, 5 = Left = -X
+    pub decals: [Option<Material>; 6],   // NormalId order: 0 = Right = +X, 1 = Top = +Y, 2 = Back = +Z, 3 = Left = -X, 4 = Bottom = -Y, 5 = Front = -Z
+    /// Decals drawn over the part's material, in NormalId order: Right, Top, Back, Left, Bottom, Front
+    pub overlays: [Option<Decal>; 6],
+}
+
+/// Struct to represent a Decal, converted into an overlay on its part's face
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decal {
+    pub material: Material,
+    pub color: Color3,
+    pub transparency: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightType {
+    Point,
+    /// Spot light shining from a face of its part, with the full cone angle in degrees
+    Spot { angle: f64, face: usize },
+    /// Light emitted by a face of its part, with the full cone angle in degrees
+    Surface { angle: f64, face: usize },
+}
+
+/// Struct to represent a light attached to a part
+#[derive(Debug, Copy, Clone)]
+pub struct Light<'a> {
+    pub light_type: LightType,
+    /// Referent of the part this light is attached to
+    pub part: &'a str,
+    /// CFrame of the Attachment this light is parented to, relative to the part
+    pub attachment: Option<CFrame>,
+    pub color: Color3,
+    pub brightness: f64,
+    /// Range in studs
+    pub range: f64,
+}
+
+/// Struct to represent the global lighting settings of the Lighting service
+#[derive(Debug, Copy, Clone)]
+pub struct Lighting {
+    /// Time of day, in hours
+    pub clock_time: f64,
+    pub geographic_latitude: f64,
+    pub ambient: Color3,
+    pub outdoor_ambient: Color3,
+    pub brightness: f64,
+    pub shadow_softness: f64,
+}
+
+/// Struct to represent a Sky, with the content URLs of its faces in Bk, Dn, Ft, Lf, Rt, Up order
+#[derive(Debug, Copy, Clone)]
+pub struct Sky<'a> {
+    pub faces: [&'a str; 6],
+}
+
+/// Struct to represent a MaterialVariant, with the material it converts into
+/// Parts use the variant if they reference it by name, and have its base material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MaterialVariant<'a> {
+    pub name: &'a str,
+    pub base_material: u32,
+    pub material: Material,
 }
 
 /// Struct to represent visual identity of a part
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct PartVisualHash {
     pub is_detail: bool,
+    pub is_self_illuminated: bool,
+    pub visgroup: Option<u32>,
     pub color: Color3,
     pub transparency: u64,
     pub reflectance: u64,
@@ -151,7 +398,8 @@ impl<'a> Part<'a> {
 
 
     fn visual_hash(&self) -> Option<PartVisualHash> {
-        if self.part_type == PartType::Part && self.shape == PartShape::Block {
+        // Overlays are placed on the faces of a single part, so parts with decals are never joined
+        if self.part_type == PartType::Part && self.shape == PartShape::Block && self.overlays.iter().all(Option::is_none) {
             let decal_hashes: Option<[Option<MaterialHash>; 6]> = try {
                 [
                     if let Some(decal) = self.decals[0] { Some(decal.material_hash()?) } else { None },
@@ -165,6 +413,8 @@ impl<'a> Part<'a> {
             if let (Some(material), Some(decals)) = (self.material.material_hash(), decal_hashes) {
                 Some(PartVisualHash {
                     is_detail: self.is_detail,
+                    is_self_illuminated: self.is_self_illuminated,
+                    visgroup: self.visgroup,
                     color: self.color,
                     transparency: self.transparency.to_bits(),
                     reflectance: self.reflectance.to_bits(),
@@ -286,9 +536,11 @@ impl<'a> Part<'a> {
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PartType {
     Part,
-    SpawnLocation,
+    /// Spawn location, with the BrickColor of its team if it is not neutral
+    SpawnLocation { team_color: Option<i64> },
     Truss,
-    Wedge
+    Wedge,
+    CornerWedge,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -321,8 +573,32 @@ pub enum Material {
     Cobblestone,
     Glass,
     ForceField,
+    Neon,
+    Basalt,
+    CrackedLava,
+    Limestone,
+    Pavement,
+    Rock,
+    Sandstone,
+    LeafyGrass,
+    Snow,
+    Mud,
+    Ground,
+    Asphalt,
+    Salt,
+    Glacier,
+    Cardboard,
+    Carpet,
+    CeramicTiles,
+    ClayRoofTiles,
+    RoofShingles,
+    Leather,
+    Plaster,
+    Rubber,
     Decal { id: u64, size_x: u64, size_y: u64 },
     Texture { id: u64, size_x: u64, size_y: u64, studs_per_u: f64, studs_per_v: f64, offset_u: f64, offset_v: f64 },
+    /// MaterialVariant, with the asset ids of its colour and normal maps, and the Enum.Material id it is based on
+    Variant { base_material: u32, color_map: u64, normal_map: Option<u64>, size_x: u64, size_y: u64, studs_per_tile: f64 },
     Custom {
         texture: &'static str,
         fill: bool,
@@ -356,11 +632,41 @@ impl Material {
             Material::Cobblestone => crate::rbx::textures::COBBLESTONE,
             Material::Glass => crate::rbx::textures::GLASS,
             Material::ForceField => crate::rbx::textures::FORCEFIELD,
+            // Materials without a bundled texture of their own use the closest match, under their own name so they can be replaced
+            Material::Neon => crate::rbx::textures::SMOOTHPLASTIC,
+            Material::Basalt => crate::rbx::textures::SLATE,
+            Material::CrackedLava => crate::rbx::textures::GRANITE,
+            Material::Limestone => crate::rbx::textures::CONCRETE,
+            Material::Pavement => crate::rbx::textures::CONCRETE,
+            Material::Rock => crate::rbx::textures::SLATE,
+            Material::Sandstone => crate::rbx::textures::SAND,
+            Material::LeafyGrass => crate::rbx::textures::GRASS,
+            Material::Snow => crate::rbx::textures::SAND,
+            Material::Mud => crate::rbx::textures::SAND,
+            Material::Ground => crate::rbx::textures::SAND,
+            Material::Asphalt => crate::rbx::textures::CONCRETE,
+            Material::Salt => crate::rbx::textures::SAND,
+            Material::Glacier => crate::rbx::textures::ICE,
+            Material::Cardboard => crate::rbx::textures::FABRIC,
+            Material::Carpet => crate::rbx::textures::FABRIC,
+            Material::CeramicTiles => crate::rbx::textures::MARBLE,
+            Material::ClayRoofTiles => crate::rbx::textures::BRICK,
+            Material::RoofShingles => crate::rbx::textures::SLATE,
+            Material::Leather => crate::rbx::textures::FABRIC,
+            Material::Plaster => crate::rbx::textures::CONCRETE,
+            Material::Rubber => crate::rbx::textures::SMOOTHPLASTIC,
             Material::Custom { texture: "decal", .. } => crate::rbx::textures::DECAL,
             Material::Custom { texture: "studs", .. } => crate::rbx::textures::STUDS,
             Material::Custom { texture: "inlet", .. } => crate::rbx::textures::INLET,
+            Material::Custom { texture: "glue", .. } => crate::rbx::textures::GLUE,
+            Material::Custom { texture: "weld", .. } => crate::rbx::textures::WELD,
+            Material::Custom { texture: "universal", .. } => crate::rbx::textures::UNIVERSAL,
+            Material::Custom { texture: "hinge", .. } => crate::rbx::textures::HINGE,
+            Material::Custom { texture: "motor", .. } => crate::rbx::textures::MOTOR,
+            Material::Custom { texture: "steppingmotor", .. } => crate::rbx::textures::STEPPINGMOTOR,
             Material::Custom { texture: "spawnlocation", .. } => crate::rbx::textures::SPAWNLOCATION,
-            Material::Custom { .. } | Material::Decal { .. } | Material::Texture { .. } => return None,
+            Material::Custom { texture: "truss", .. } => crate::rbx::textures::TRUSS,
+            Material::Custom { .. } | Material::Decal { .. } | Material::Texture { .. } | Material::Variant { .. } => return None,
         })
     }
 }
@@ -369,6 +675,7 @@ impl Material {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum MaterialHash {
     Regular(u32),
+    Variant { color_map: u64, normal_map: Option<u64>, studs_per_tile: u64 },
     Custom {
         texture: &'static str,
         size_x: u64,
@@ -395,12 +702,35 @@ impl Material {
             864 => Some(Pebble),
             1296 => Some(Sand),
             1312 => Some(Fabric),
-            272 | 288 => Some(SmoothPlastic),
+            272 => Some(SmoothPlastic),
             1088 => Some(Metal),
             528 => Some(WoodPlanks),
             880 => Some(Cobblestone),
             1568 => Some(Glass),
             1584 => Some(ForceField),
+            288 => Some(Neon),
+            788 => Some(Basalt),
+            804 => Some(CrackedLava),
+            820 => Some(Limestone),
+            836 => Some(Pavement),
+            896 => Some(Rock),
+            912 => Some(Sandstone),
+            1284 => Some(LeafyGrass),
+            1328 => Some(Snow),
+            1344 => Some(Mud),
+            1360 => Some(Ground),
+            1376 => Some(Asphalt),
+            1392 => Some(Salt),
+            1552 => Some(Glacier),
+            2304 => Some(Cardboard),
+            2305 => Some(Carpet),
+            2306 => Some(CeramicTiles),
+            2307 => Some(ClayRoofTiles),
+            2308 => Some(RoofShingles),
+            2309 => Some(Leather),
+            2310 => Some(Plaster),
+            2311 => Some(Rubber),
+            1792 | 2048 => Some(SmoothPlastic),  // Air and Water are terrain materials, parts using them are rendered as SmoothPlastic
             _ => None
         }
     }
@@ -428,8 +758,31 @@ impl Material {
             Material::Cobblestone => 1024,
             Material::Glass => 512,
             Material::ForceField => 1024,
+            Material::Neon => 32,
+            Material::Basalt => 1024,
+            Material::CrackedLava => 1024,
+            Material::Limestone => 1024,
+            Material::Pavement => 1024,
+            Material::Rock => 1024,
+            Material::Sandstone => 1024,
+            Material::LeafyGrass => 1024,
+            Material::Snow => 1024,
+            Material::Mud => 1024,
+            Material::Ground => 1024,
+            Material::Asphalt => 1024,
+            Material::Salt => 1024,
+            Material::Glacier => 1024,
+            Material::Cardboard => 512,
+            Material::Carpet => 512,
+            Material::CeramicTiles => 1024,
+            Material::ClayRoofTiles => 1024,
+            Material::RoofShingles => 1024,
+            Material::Leather => 512,
+            Material::Plaster => 1024,
+            Material::Rubber => 32,
             Material::Decal { size_x, .. } => size_x,
             Material::Texture { size_x, .. } => size_x,
+            Material::Variant { size_x, .. } => size_x,
             Material::Custom { size_x, .. } => size_x,
         }
     }
@@ -457,12 +810,88 @@ impl Material {
             Material::Cobblestone => 1024,
             Material::Glass => 512,
             Material::ForceField => 1024,
+            Material::Neon => 32,
+            Material::Basalt => 1024,
+            Material::CrackedLava => 1024,
+            Material::Limestone => 1024,
+            Material::Pavement => 1024,
+            Material::Rock => 1024,
+            Material::Sandstone => 1024,
+            Material::LeafyGrass => 1024,
+            Material::Snow => 1024,
+            Material::Mud => 1024,
+            Material::Ground => 1024,
+            Material::Asphalt => 1024,
+            Material::Salt => 1024,
+            Material::Glacier => 1024,
+            Material::Cardboard => 512,
+            Material::Carpet => 512,
+            Material::CeramicTiles => 1024,
+            Material::ClayRoofTiles => 1024,
+            Material::RoofShingles => 1024,
+            Material::Leather => 512,
+            Material::Plaster => 1024,
+            Material::Rubber => 32,
             Material::Decal { size_y, .. } => size_y,
             Material::Texture { size_y, .. } => size_y,
+            Material::Variant { size_y, .. } => size_y,
             Material::Custom { size_y, .. } => size_y
         }
     }
 
+    /// Source engine surface property (footsteps, impacts and friction) of this material
+    pub fn surface_prop(self) -> Option<&'static str> {
+        match self {
+            Material::Plastic => Some("plastic"),
+            Material::Wood => Some("wood"),
+            Material::Slate => Some("rock"),
+            Material::Concrete => Some("concrete"),
+            Material::CorrodedMetal => Some("metal"),
+            Material::DiamondPlate => Some("metal"),
+            Material::Foil => Some("metal"),
+            Material::Grass => Some("grass"),
+            Material::Ice => Some("ice"),
+            Material::Marble => Some("rock"),
+            Material::Granite => Some("rock"),
+            Material::Brick => Some("brick"),
+            Material::Pebble => Some("gravel"),
+            Material::Sand => Some("sand"),
+            Material::Fabric => Some("carpet"),
+            Material::SmoothPlastic => Some("plastic"),
+            Material::Metal => Some("metal"),
+            Material::WoodPlanks => Some("wood_plank"),
+            Material::Cobblestone => Some("rock"),
+            Material::Glass => Some("glass"),
+            Material::Neon => Some("plastic"),
+            Material::Basalt => Some("rock"),
+            Material::CrackedLava => Some("rock"),
+            Material::Limestone => Some("rock"),
+            Material::Pavement => Some("concrete"),
+            Material::Rock => Some("rock"),
+            Material::Sandstone => Some("rock"),
+            Material::LeafyGrass => Some("grass"),
+            Material::Snow => Some("snow"),
+            Material::Mud => Some("mud"),
+            Material::Ground => Some("dirt"),
+            Material::Asphalt => Some("concrete"),
+            Material::Salt => Some("sand"),
+            Material::Glacier => Some("ice"),
+            Material::Cardboard => Some("cardboard"),
+            Material::Carpet => Some("carpet"),
+            Material::CeramicTiles => Some("tile"),
+            Material::ClayRoofTiles => Some("tile"),
+            Material::RoofShingles => Some("tile"),
+            Material::Leather => Some("carpet"),
+            Material::Plaster => Some("plaster"),
+            Material::Rubber => Some("rubber"),
+            Material::ForceField => None,
+            Material::Custom { texture: "studs" | "inlet" | "glue" | "weld" | "universal" | "hinge" | "motor" | "steppingmotor" | "spawnlocation", .. } => Some("plastic"),
+            Material::Custom { texture: "truss", .. } => Some("metal"),
+            Material::Variant { base_material, .. } => Material::from_id(base_material)?.surface_prop(),
+            Material::Custom { .. } | Material::Decal { .. } | Material::Texture { .. } => None,
+        }
+    }
+
     pub fn material_hash(self) -> Option<MaterialHash> {
         match self {
             Material::Plastic => Some(MaterialHash::Regular(256)),
@@ -486,8 +915,31 @@ impl Material {
             Material::Cobblestone => Some(MaterialHash::Regular(880)),
             Material::Glass => Some(MaterialHash::Regular(1568)),
             Material::ForceField => Some(MaterialHash::Regular(1584)),
+            Material::Neon => Some(MaterialHash::Regular(288)),
+            Material::Basalt => Some(MaterialHash::Regular(788)),
+            Material::CrackedLava => Some(MaterialHash::Regular(804)),
+            Material::Limestone => Some(MaterialHash::Regular(820)),
+            Material::Pavement => Some(MaterialHash::Regular(836)),
+            Material::Rock => Some(MaterialHash::Regular(896)),
+            Material::Sandstone => Some(MaterialHash::Regular(912)),
+            Material::LeafyGrass => Some(MaterialHash::Regular(1284)),
+            Material::Snow => Some(MaterialHash::Regular(1328)),
+            Material::Mud => Some(MaterialHash::Regular(1344)),
+            Material::Ground => Some(MaterialHash::Regular(1360)),
+            Material::Asphalt => Some(MaterialHash::Regular(1376)),
+            Material::Salt => Some(MaterialHash::Regular(1392)),
+            Material::Glacier => Some(MaterialHash::Regular(1552)),
+            Material::Cardboard => Some(MaterialHash::Regular(2304)),
+            Material::Carpet => Some(MaterialHash::Regular(2305)),
+            Material::CeramicTiles => Some(MaterialHash::Regular(2306)),
+            Material::ClayRoofTiles => Some(MaterialHash::Regular(2307)),
+            Material::RoofShingles => Some(MaterialHash::Regular(2308)),
+            Material::Leather => Some(MaterialHash::Regular(2309)),
+            Material::Plaster => Some(MaterialHash::Regular(2310)),
+            Material::Rubber => Some(MaterialHash::Regular(2311)),
             Material::Decal { .. } => None,
             Material::Texture { .. } => None,
+            Material::Variant { color_map, normal_map, studs_per_tile, .. } => Some(MaterialHash::Variant { color_map, normal_map, studs_per_tile: studs_per_tile.to_bits() }),
             Material::Custom { texture, fill, size_x, size_y, .. } => {
                 if !fill {
                     Some(MaterialHash::Custom { texture, size_x, size_y, })
@@ -523,9 +975,33 @@ impl Display for Material {
             Material::Cobblestone => write!(f, "cobblestone"),
             Material::Glass => write!(f, "glass"),
             Material::ForceField => write!(f, "forcefield"),
+            Material::Neon => write!(f, "neon"),
+            Material::Basalt => write!(f, "basalt"),
+            Material::CrackedLava => write!(f, "crackedlava"),
+            Material::Limestone => write!(f, "limestone"),
+            Material::Pavement => write!(f, "pavement"),
+            Material::Rock => write!(f, "rock"),
+            Material::Sandstone => write!(f, "sandstone"),
+            Material::LeafyGrass => write!(f, "leafygrass"),
+            Material::Snow => write!(f, "snow"),
+            Material::Mud => write!(f, "mud"),
+            Material::Ground => write!(f, "ground"),
+            Material::Asphalt => write!(f, "asphalt"),
+            Material::Salt => write!(f, "salt"),
+            Material::Glacier => write!(f, "glacier"),
+            Material::Cardboard => write!(f, "cardboard"),
+            Material::Carpet => write!(f, "carpet"),
+            Material::CeramicTiles => write!(f, "ceramictiles"),
+            Material::ClayRoofTiles => write!(f, "clayrooftiles"),
+            Material::RoofShingles => write!(f, "roofshingles"),
+            Material::Leather => write!(f, "leather"),
+            Material::Plaster => write!(f, "plaster"),
+            Material::Rubber => write!(f, "rubber"),
             Material::Custom { texture, .. } => write!(f, "{}", texture),
             Material::Decal { id, .. } => write!(f, "decal_{}", id),
-            Material::Texture { id, .. } => write!(f, "texture_{}", id)
+            Material::Texture { id, .. } => write!(f, "texture_{}", id),
+            Material::Variant { color_map, normal_map: None, .. } => write!(f, "variant_{}", color_map),
+            Material::Variant { color_map, normal_map: Some(normal_map), .. } => write!(f, "variant_{}_{}", color_map, normal_map),
         }
     }
 }
@@ -542,6 +1018,14 @@ impl Color3 {
     pub fn white() -> Color3 {
         Color3::from(u32::MAX)
     }
+
+    pub fn from_floats(red: f64, green: f64, blue: f64) -> Color3 {
+        Color3 {
+            red: (red * 255.0).round().clamp(0.0, 255.0) as u8,
+            green: (green * 255.0).round().clamp(0.0, 255.0) as u8,
+            blue: (blue * 255.0).round().clamp(0.0, 255.0) as u8,
+        }
+    }
 }
 
 impl From<u32> for Color3 {
@@ -589,6 +1073,22 @@ impl Vector3 {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
+    pub fn unit(self) -> Vector3 {
+        self / self.magnitude()
+    }
+
+    pub fn dot(self, other: Vector3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vector3) -> Vector3 {
+        Vector3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
     pub fn closest_axis(self) -> Vector3 {
         if self.x.abs() >= self.y.abs() && self.x.abs() >= self.z.abs() {
             if self.x.is_sign_positive() {
@@ -810,6 +1310,16 @@ impl CFrame {
         }
     }
 
+    /// Rotates a vector from this CFrame's object space to world space, without translating it
+    pub fn vector_to_world_space(self, vector: Vector3) -> Vector3 {
+        vector * CFrame { position: Vector3 { x: 0.0, y: 0.0, z: 0.0 }, rot_matrix: self.rot_matrix }
+    }
+
+    /// Rotates a vector from world space to this CFrame's object space, without translating it
+    pub fn vector_to_object_space(self, vector: Vector3) -> Vector3 {
+        vector / CFrame { position: Vector3 { x: 0.0, y: 0.0, z: 0.0 }, rot_matrix: self.rot_matrix }
+    }
+
     pub fn transpose(self) -> CFrame {
         let m = self.rot_matrix;
         CFrame {
@@ -4,10 +4,10 @@ use std::io::Write;
 
 pub trait VMFTexture: PartialEq {
     fn name(&self) -> String;
-    fn scale_x(&self, side: Side) -> f64;
-    fn scale_z(&self, side: Side) -> f64;
-    fn offset_x(&self, side: Side) -> f64;
-    fn offset_y(&self, side: Side) -> f64;
+    fn scale_x(&self, side: &Side) -> f64;
+    fn scale_z(&self, side: &Side) -> f64;
+    fn offset_x(&self, side: &Side) -> f64;
+    fn offset_y(&self, side: &Side) -> f64;
 }
 
 pub struct TextureMap<T: VMFTexture> {
@@ -59,16 +59,41 @@ pub struct TextureID {
 #[derive(Debug, Clone)]
 pub struct Solid {
     pub id: u32,
-    pub sides: Vec<Side>
+    pub sides: Vec<Side>,
+    pub visgroups: Vec<u32>
 }
 
+/// Struct to represent hammer visgroups, which may be nested
+#[derive(Debug, Clone)]
+pub struct Visgroup<'a> {
+    pub id: u32,
+    pub name: &'a str,
+    pub children: Vec<Visgroup<'a>>
+}
+
+/// Struct to represent source engine entities, both point entities and brush entities
+#[derive(Debug, Clone)]
+pub struct Entity<'a> {
+    pub id: u32,
+    pub classname: &'a str,
+    /// Keyvalues other than id, classname and origin
+    pub keyvalues: Vec<(&'a str, String)>,
+    pub origin: Option<[f64; 3]>,
+    pub solids: Vec<Solid>,
+    /// Outputs, as output name and "target,input,parameter,delay,times to fire"
+    pub connections: Vec<(&'a str, String)>,
+}
 
 /// Struct to represent source engine brush displacement
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Displacement {
-    pub offsets: [[f64; 15]; 5],
-    pub offset_normals: [[f64; 15]; 5],
+    pub power: u32,
     pub start_position: [f64; 3],
+    /// Direction each vertex is displaced in, in rows of `2^power + 1` vertices starting from `start_position`
+    pub normals: Vec<Vec<[f64; 3]>>,
+    /// Distance each vertex is displaced
+    pub distances: Vec<Vec<f64>>,
+    pub face_normal: [f64; 3],
 }
 
 /// Direction from which to apply texture
@@ -80,38 +105,43 @@ pub enum TextureFace {
     Z_NEG,
     Y_POS,
     Y_NEG,
+    /// Texture axes aligned to the face of a (rotated) part, with the texture anchored at `origin`
+    ALIGNED { u_axis: [f64; 3], v_axis: [f64; 3], origin: [f64; 3] },
 }
 
 impl TextureFace {
-    pub fn u_axis(self) -> &'static str {
+    pub fn u_axis(self) -> [f64; 3] {
         match self {
-            TextureFace::X_POS => "0 1 0",
-            TextureFace::X_NEG => "0 -1 0",
-            TextureFace::Z_POS => "1 0 0",
-            TextureFace::Z_NEG => "-1 0 0",
-            TextureFace::Y_POS => "0 1 0",
-            TextureFace::Y_NEG => "0 -1 0",
+            TextureFace::X_POS => [0.0, 1.0, 0.0],
+            TextureFace::X_NEG => [0.0, -1.0, 0.0],
+            TextureFace::Z_POS => [1.0, 0.0, 0.0],
+            TextureFace::Z_NEG => [-1.0, 0.0, 0.0],
+            TextureFace::Y_POS => [0.0, 1.0, 0.0],
+            TextureFace::Y_NEG => [0.0, -1.0, 0.0],
+            TextureFace::ALIGNED { u_axis, .. } => u_axis,
         }
     }
 
-    pub fn v_axis(self) -> &'static str {
+    pub fn v_axis(self) -> [f64; 3] {
         match self {
-            TextureFace::X_POS => "0 0 -1",
-            TextureFace::X_NEG => "0 0 -1",
-            TextureFace::Z_POS => "0 0 -1",
-            TextureFace::Z_NEG => "0 0 -1",
-            TextureFace::Y_POS => "1 0 0",
-            TextureFace::Y_NEG => "1 0 0",
+            TextureFace::X_POS => [0.0, 0.0, -1.0],
+            TextureFace::X_NEG => [0.0, 0.0, -1.0],
+            TextureFace::Z_POS => [0.0, 0.0, -1.0],
+            TextureFace::Z_NEG => [0.0, 0.0, -1.0],
+            TextureFace::Y_POS => [1.0, 0.0, 0.0],
+            TextureFace::Y_NEG => [1.0, 0.0, 0.0],
+            TextureFace::ALIGNED { v_axis, .. } => v_axis,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Side {
     pub id: u32,
     pub texture: TextureID,
     pub texture_face: TextureFace,
     pub plane: [[f64; 3]; 3],
+    pub smoothing_groups: u32,
     pub displacement: Option<Displacement>
 }
 
@@ -143,11 +173,60 @@ impl<T: Write> VMFBuilder<T> {
         Ok(self)
     }
 
-    pub fn visgroups(mut self) -> std::io::Result<Self> {
-        write!(self.0, "visgroups{{}}\n")?;
+    pub fn visgroups(mut self, visgroups: &[Visgroup]) -> std::io::Result<Self> {
+        write!(self.0, "visgroups\n{{\n")?;
+        for visgroup in visgroups {
+            self.visgroup(visgroup, 1)?;
+        }
+        writeln!(self.0, "}}")?;
         Ok(self)
     }
 
+    fn visgroup(&mut self, visgroup: &Visgroup, depth: usize) -> std::io::Result<()> {
+        let indent = "\t".repeat(depth);
+        let color = visgroup_color(visgroup.id);
+        write!(
+            self.0,
+            "{indent}visgroup\n\
+            {indent}{{\n\
+                {indent}\t\"name\" \"{}\"\n\
+                {indent}\t\"visgroupid\" \"{}\"\n\
+                {indent}\t\"color\" \"{} {} {}\"\n",
+            visgroup.name.replace('"', "'"),     // VMF strings can not contain quotes
+            visgroup.id,
+            color[0], color[1], color[2],
+            indent = indent
+        )?;
+        for child in &visgroup.children {
+            self.visgroup(child, depth + 1)?;
+        }
+        writeln!(self.0, "{}}}", indent)
+    }
+
+    /// Writes an editor block placing an object in the given visgroups
+    fn editor(&mut self, visgroups: &[u32], depth: usize) -> std::io::Result<()> {
+        let indent = "\t".repeat(depth);
+        let color = visgroups.first().copied().map(visgroup_color).unwrap_or([220, 220, 220]);
+        write!(
+            self.0,
+            "{indent}editor\n\
+            {indent}{{\n\
+                {indent}\t\"color\" \"{} {} {}\"\n",
+            color[0], color[1], color[2],
+            indent = indent
+        )?;
+        for visgroup in visgroups {
+            writeln!(self.0, "{}\t\"visgroupid\" \"{}\"", indent, visgroup)?;
+        }
+        write!(
+            self.0,
+            "{indent}\t\"visgroupshown\" \"1\"\n\
+            {indent}\t\"visgroupautoshown\" \"1\"\n\
+            {indent}}}\n",
+            indent = indent
+        )
+    }
+
     pub fn viewsettings(mut self) -> std::io::Result<Self> {
         write!(self.0, "viewsettings{{}}\n")?;
         Ok(self)
@@ -163,176 +242,183 @@ impl<T: Write> VMFBuilder<T> {
                 \t\"classname\" \"worldspawn\"\n\
                 \t\"skyname\" \"{}\"\n",
             map_version,
-            skyname
+            skyname.replace('"', "'")
         )?;
 
         for solid in solids.into_iter() {
             let solid: Solid = solid;   // Type hint for IDE
-            write!(
-                self.0,
-                "\tsolid\n\
-                \t{{\n\
-                    \t\t\"id\" \"{}\"\n",
-                solid.id,
-            )?;
-            for side in solid.sides {
-                let texture = texture_map.get_texture(side.texture).unwrap();
-                write!(
-                    self.0,
-                    "\t\tside\n\
-                    \t\t{{\n\
-                        \t\t\t\"id\" \"{}\"\n\
-                        \t\t\t\"plane\" \"({} {} {}) ({} {} {}) ({} {} {})\"\n\
-                        \t\t\t\"material\" \"{}\"\n\
-                        \t\t\t\"uaxis\" \"[{} {}] {}\"\n\
-                        \t\t\t\"vaxis\" \"[{} {}] {}\"\n\
-                        \t\t\t\"rotation\" \"0\"\n\
-                        \t\t\t\"lightmapscale\" \"16\"\n\
-                        \t\t\t\"smoothing_groups\" \"0\"\n",
-                    side.id,
-                    side.plane[0][0], side.plane[0][1], side.plane[0][2], side.plane[1][0], side.plane[1][1], side.plane[1][2], side.plane[2][0], side.plane[2][1], side.plane[2][2],
-                    texture.name(),
-                    side.texture_face.u_axis(), texture.offset_x(side), texture.scale_x(side),
-                    side.texture_face.v_axis(), texture.offset_y(side), texture.scale_z(side)
-                )?;
-                if let Some(displacement) = side.displacement {
-                    write!(
-                        self.0,
-                        r#"
-                        dispinfo
-                        {{
-                            "power" "2"
-                            "startposition" "[{} {} {}]"
-                            "flags" "0"
-                            "elevation" "0"
-                            "subdiv" "1"
-                            normals
-                            {{
-                                "row0" "0 0 0 0 0 0 0 0 0 0 0 0 0 0 0"
-                                "row1" "0 0 0 0 0 0 0 0 0 0 0 0 0 0 0"
-                                "row2" "0 0 0 0 0 0 0 0 0 0 0 0 0 0 0"
-                                "row3" "0 0 0 0 0 0 0 0 0 0 0 0 0 0 0"
-                                "row4" "0 0 0 0 0 0 0 0 0 0 0 0 0 0 0"
-                            }}
-                            distances
-                            {{
-                                "row0" "1e-05 1e-05 1e-05 1e-05 1e-05"
-                                "row1" "1e-05 1e-05 1e-05 1e-05 1e-05"
-                                "row2" "1e-05 1e-05 1e-05 1e-05 1e-05"
-                                "row3" "1e-05 1e-05 1e-05 1e-05 1e-05"
-                                "row4" "1e-05 1e-05 1e-05 1e-05 1e-05"
-                            }}
-                            offsets
-                            {{
-                                "row0" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row1" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row2" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row3" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row4" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                            }}
-                            offset_normals
-                            {{
-                                "row0" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row1" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row2" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row3" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                                "row4" "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {}"
-                            }}
-                            alphas
-                            {{
-                                "row0" "0 0 0 0 0"
-                                "row1" "0 0 0 0 0"
-                                "row2" "0 0 0 0 0"
-                                "row3" "0 0 0 0 0"
-                                "row4" "0 0 0 0 0"
-                            }}
-                            triangle_tags
-                            {{
-                                "row0" "0 0 0 0 0 0 0 0"
-                                "row1" "0 0 0 0 0 0 0 0"
-                                "row2" "0 0 0 0 0 0 0 0"
-                                "row3" "0 0 0 0 0 0 0 0"
-                            }}
-                            allowed_verts
-                            {{
-                                "10" "-1 -1 -1 -1 -1 -1 -1 -1 -1 -1"
-                            }}
-                        }}
-                        "#,
-                        displacement.start_position[0],
-                        displacement.start_position[1],
-                        displacement.start_position[2],
-                        displacement.offsets[0][0], displacement.offsets[0][1], displacement.offsets[0][2], displacement.offsets[0][3], displacement.offsets[0][4], displacement.offsets[0][5], displacement.offsets[0][6], displacement.offsets[0][7], displacement.offsets[0][8], displacement.offsets[0][9], displacement.offsets[0][10], displacement.offsets[0][11], displacement.offsets[0][12], displacement.offsets[0][13], displacement.offsets[0][14],
-                        displacement.offsets[1][0], displacement.offsets[1][1], displacement.offsets[1][2], displacement.offsets[1][3], displacement.offsets[1][4], displacement.offsets[1][5], displacement.offsets[1][6], displacement.offsets[1][7], displacement.offsets[1][8], displacement.offsets[1][9], displacement.offsets[1][10], displacement.offsets[1][11], displacement.offsets[1][12], displacement.offsets[1][13], displacement.offsets[1][14],
-                        displacement.offsets[2][0], displacement.offsets[2][1], displacement.offsets[2][2], displacement.offsets[2][3], displacement.offsets[2][4], displacement.offsets[2][5], displacement.offsets[2][6], displacement.offsets[2][7], displacement.offsets[2][8], displacement.offsets[2][9], displacement.offsets[2][10], displacement.offsets[2][11], displacement.offsets[2][12], displacement.offsets[2][13], displacement.offsets[2][14],
-                        displacement.offsets[3][0], displacement.offsets[3][1], displacement.offsets[3][2], displacement.offsets[3][3], displacement.offsets[3][4], displacement.offsets[3][5], displacement.offsets[3][6], displacement.offsets[3][7], displacement.offsets[3][8], displacement.offsets[3][9], displacement.offsets[3][10], displacement.offsets[3][11], displacement.offsets[3][12], displacement.offsets[3][13], displacement.offsets[3][14],
-                        displacement.offsets[4][0], displacement.offsets[4][1], displacement.offsets[4][2], displacement.offsets[4][3], displacement.offsets[4][4], displacement.offsets[4][5], displacement.offsets[4][6], displacement.offsets[4][7], displacement.offsets[4][8], displacement.offsets[4][9], displacement.offsets[4][10], displacement.offsets[4][11], displacement.offsets[4][12], displacement.offsets[4][13], displacement.offsets[4][14],
-                        displacement.offset_normals[0][0], displacement.offset_normals[0][1], displacement.offset_normals[0][2], displacement.offset_normals[0][3], displacement.offset_normals[0][4], displacement.offset_normals[0][5], displacement.offset_normals[0][6], displacement.offset_normals[0][7], displacement.offset_normals[0][8], displacement.offset_normals[0][9], displacement.offset_normals[0][10], displacement.offset_normals[0][11], displacement.offset_normals[0][12], displacement.offset_normals[0][13], displacement.offset_normals[0][14],
-                        displacement.offset_normals[1][0], displacement.offset_normals[1][1], displacement.offset_normals[1][2], displacement.offset_normals[1][3], displacement.offset_normals[1][4], displacement.offset_normals[1][5], displacement.offset_normals[1][6], displacement.offset_normals[1][7], displacement.offset_normals[1][8], displacement.offset_normals[1][9], displacement.offset_normals[1][10], displacement.offset_normals[1][11], displacement.offset_normals[1][12], displacement.offset_normals[1][13], displacement.offset_normals[1][14],
-                        displacement.offset_normals[2][0], displacement.offset_normals[2][1], displacement.offset_normals[2][2], displacement.offset_normals[2][3], displacement.offset_normals[2][4], displacement.offset_normals[2][5], displacement.offset_normals[2][6], displacement.offset_normals[2][7], displacement.offset_normals[2][8], displacement.offset_normals[2][9], displacement.offset_normals[2][10], displacement.offset_normals[2][11], displacement.offset_normals[2][12], displacement.offset_normals[2][13], displacement.offset_normals[2][14],
-                        displacement.offset_normals[3][0], displacement.offset_normals[3][1], displacement.offset_normals[3][2], displacement.offset_normals[3][3], displacement.offset_normals[3][4], displacement.offset_normals[3][5], displacement.offset_normals[3][6], displacement.offset_normals[3][7], displacement.offset_normals[3][8], displacement.offset_normals[3][9], displacement.offset_normals[3][10], displacement.offset_normals[3][11], displacement.offset_normals[3][12], displacement.offset_normals[3][13], displacement.offset_normals[3][14],
-                        displacement.offset_normals[4][0], displacement.offset_normals[4][1], displacement.offset_normals[4][2], displacement.offset_normals[4][3], displacement.offset_normals[4][4], displacement.offset_normals[4][5], displacement.offset_normals[4][6], displacement.offset_normals[4][7], displacement.offset_normals[4][8], displacement.offset_normals[4][9], displacement.offset_normals[4][10], displacement.offset_normals[4][11], displacement.offset_normals[4][12], displacement.offset_normals[4][13], displacement.offset_normals[4][14],
-                    )?;
-                }
-                write!(self.0, "\t\t}}\n")?;
-            }
-
-            write!(
-                self.0,
-                "\t}}\n"
-            )?;
+            self.solid(solid, texture_map)?;
         }
 
         write!(self.0, "}}\n")?;
         Ok(self)
     }
 
-    pub fn detail<'a, I: IntoIterator<Item=(u32, Solid)>, Texture: VMFTexture>(mut self, details: I, texture_map: &TextureMap<Texture>) -> std::io::Result<Self> {  // TODO: Upgrade to support other entities
-        for (entity_id, detail_brush) in details {
+    pub fn entities<'a, I: IntoIterator<Item=Entity<'a>>, Texture: VMFTexture>(mut self, entities: I, texture_map: &TextureMap<Texture>) -> std::io::Result<Self> {
+        for entity in entities {
             write!(
                 self.0,
                 "entity\n\
             {{\n\
                 \t\"id\" \"{}\"\n\
-                \t\"classname\" \"func_detail\"\n",
-                entity_id
+                \t\"classname\" \"{}\"\n",
+                entity.id,
+                entity.classname
             )?;
-            write!(
-                self.0,
-                "\tsolid\n\
-                \t{{\n\
-                    \t\t\"id\" \"{}\"\n",
-                detail_brush.id,
-            )?;
-            for side in detail_brush.sides {
-                let texture = texture_map.get_texture(side.texture).unwrap();
-                write!(
-                    self.0,
-                    "\t\tside\n\
-                    \t\t{{\n\
-                        \t\t\t\"id\" \"{}\"\n\
-                        \t\t\t\"plane\" \"({} {} {}) ({} {} {}) ({} {} {})\"\n\
-                        \t\t\t\"material\" \"{}\"\n\
-                        \t\t\t\"uaxis\" \"[{} {}] {}\"\n\
-                        \t\t\t\"vaxis\" \"[{} {}] {}\"\n\
-                        \t\t\t\"rotation\" \"0\"\n\
-                        \t\t\t\"lightmapscale\" \"16\"\n\
-                        \t\t\t\"smoothing_groups\" \"0\"\n\
-                    \t\t}}\n",
-                    side.id,
-                    side.plane[0][0], side.plane[0][1], side.plane[0][2], side.plane[1][0], side.plane[1][1], side.plane[1][2], side.plane[2][0], side.plane[2][1], side.plane[2][2],
-                    texture.name(),
-                    side.texture_face.u_axis(), texture.offset_x(side), texture.scale_x(side),
-                    side.texture_face.v_axis(), texture.offset_y(side), texture.scale_z(side)
-                )?;
+            // VMF strings can not contain quotes, and keyvalues may hold Roblox instance names or asset paths
+            for (key, value) in &entity.keyvalues {
+                writeln!(self.0, "\t\"{}\" \"{}\"", key.replace('"', "'"), value.replace('"', "'"))?;
             }
+            if let Some(origin) = entity.origin {
+                writeln!(self.0, "\t\"origin\" \"{} {} {}\"", origin[0], origin[1], origin[2])?;
+            }
+            if !entity.connections.is_empty() {
+                write!(self.0, "\tconnections\n\t{{\n")?;
+                for (output, target) in &entity.connections {
+                    writeln!(self.0, "\t\t\"{}\" \"{}\"", output.replace('"', "'"), target.replace('"', "'"))?;
+                }
+                writeln!(self.0, "\t}}")?;
+            }
+            let mut visgroups = entity.solids.iter().flat_map(|solid| solid.visgroups.iter().copied()).collect::<Vec<_>>();
+            visgroups.sort_unstable();
+            visgroups.dedup();
+            for solid in entity.solids {
+                self.solid(solid, texture_map)?;
+            }
+            if !visgroups.is_empty() {
+                self.editor(&visgroups, 1)?;
+            }
+            writeln!(self.0, "}}")?;
+        }
+        Ok(self)
+    }
 
+    fn solid<Texture: VMFTexture>(&mut self, solid: Solid, texture_map: &TextureMap<Texture>) -> std::io::Result<()> {
+        write!(
+            self.0,
+            "\tsolid\n\
+            \t{{\n\
+                \t\t\"id\" \"{}\"\n",
+            solid.id,
+        )?;
+        for side in solid.sides {
+            let texture = texture_map.get_texture(side.texture).unwrap();
+            let (u_axis, v_axis) = (side.texture_face.u_axis(), side.texture_face.v_axis());
             write!(
                 self.0,
-                   "\t}}\n\
-                    }}\n"
+                "\t\tside\n\
+                \t\t{{\n\
+                    \t\t\t\"id\" \"{}\"\n\
+                    \t\t\t\"plane\" \"({} {} {}) ({} {} {}) ({} {} {})\"\n\
+                    \t\t\t\"material\" \"{}\"\n\
+                    \t\t\t\"uaxis\" \"[{} {} {} {}] {}\"\n\
+                    \t\t\t\"vaxis\" \"[{} {} {} {}] {}\"\n\
+                    \t\t\t\"rotation\" \"0\"\n\
+                    \t\t\t\"lightmapscale\" \"16\"\n\
+                    \t\t\t\"smoothing_groups\" \"{}\"\n",
+                side.id,
+                side.plane[0][0], side.plane[0][1], side.plane[0][2], side.plane[1][0], side.plane[1][1], side.plane[1][2], side.plane[2][0], side.plane[2][1], side.plane[2][2],
+                texture.name(),
+                u_axis[0], u_axis[1], u_axis[2], texture.offset_x(&side), texture.scale_x(&side),
+                v_axis[0], v_axis[1], v_axis[2], texture.offset_y(&side), texture.scale_z(&side),
+                side.smoothing_groups
             )?;
+            if let Some(displacement) = &side.displacement {
+                self.displacement(displacement)?;
+            }
+            write!(self.0, "\t\t}}\n")?;
         }
-        Ok(self)
+        if !solid.visgroups.is_empty() {
+            self.editor(&solid.visgroups, 2)?;
+        }
+
+        write!(
+            self.0,
+            "\t}}\n"
+        )
     }
-}
\ No newline at end of file
+
+    fn displacement(&mut self, displacement: &Displacement) -> std::io::Result<()> {
+        let vertex_count = (1 << displacement.power) + 1;
+        write!(
+            self.0,
+            "\t\t\tdispinfo\n\
+            \t\t\t{{\n\
+                \t\t\t\t\"power\" \"{}\"\n\
+                \t\t\t\t\"startposition\" \"[{} {} {}]\"\n\
+                \t\t\t\t\"flags\" \"0\"\n\
+                \t\t\t\t\"elevation\" \"0\"\n\
+                \t\t\t\t\"subdiv\" \"1\"\n",
+            displacement.power,
+            displacement.start_position[0], displacement.start_position[1], displacement.start_position[2]
+        )?;
+
+        write!(self.0, "\t\t\t\tnormals\n\t\t\t\t{{\n")?;
+        for (index, row) in displacement.normals.iter().enumerate() {
+            let row = row.iter().map(|normal| format!("{} {} {}", normal[0], normal[1], normal[2])).collect::<Vec<_>>().join(" ");
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, row)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        write!(self.0, "\t\t\t\tdistances\n\t\t\t\t{{\n")?;
+        for (index, row) in displacement.distances.iter().enumerate() {
+            let row = row.iter().map(f64::to_string).collect::<Vec<_>>().join(" ");
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, row)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        // Vertices are only displaced along their normals, offsets are left at zero
+        let offsets = vec!["0 0 0"; vertex_count].join(" ");
+        write!(self.0, "\t\t\t\toffsets\n\t\t\t\t{{\n")?;
+        for index in 0..vertex_count {
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, offsets)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        let face_normal = format!("{} {} {}", displacement.face_normal[0], displacement.face_normal[1], displacement.face_normal[2]);
+        let offset_normals = vec![&*face_normal; vertex_count].join(" ");
+        write!(self.0, "\t\t\t\toffset_normals\n\t\t\t\t{{\n")?;
+        for index in 0..vertex_count {
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, offset_normals)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        let alphas = vec!["0"; vertex_count].join(" ");
+        write!(self.0, "\t\t\t\talphas\n\t\t\t\t{{\n")?;
+        for index in 0..vertex_count {
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, alphas)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        // Two triangles per quad
+        let triangle_tags = vec!["0"; (vertex_count - 1) * 2].join(" ");
+        write!(self.0, "\t\t\t\ttriangle_tags\n\t\t\t\t{{\n")?;
+        for index in 0..(vertex_count - 1) {
+            writeln!(self.0, "\t\t\t\t\t\"row{}\" \"{}\"", index, triangle_tags)?;
+        }
+        writeln!(self.0, "\t\t\t\t}}")?;
+
+        write!(
+            self.0,
+            "\t\t\t\tallowed_verts\n\
+            \t\t\t\t{{\n\
+                \t\t\t\t\t\"10\" \"-1 -1 -1 -1 -1 -1 -1 -1 -1 -1\"\n\
+            \t\t\t\t}}\n\
+            \t\t\t}}\n"
+        )
+    }
+}
+
+/// Colour of a visgroup, spread around the colour wheel by id
+fn visgroup_color(id: u32) -> [u8; 3] {
+    let hue = (id as f64 * 0.618033988749895).fract() * 6.0;
+    let rising = ((1.0 - (hue % 2.0 - 1.0).abs()) * 255.0) as u8;
+    match hue as u32 {
+        0 => [255, rising, 0],
+        1 => [rising, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, rising, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, rising],
+    }
+}
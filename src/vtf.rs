@@ -0,0 +1,471 @@
+use std::io::Write;
+
+/// Texture clamps instead of wrapping along the S (horizontal) axis
+pub const FLAG_CLAMP_S: u32 = 0x0004;
+/// Texture clamps instead of wrapping along the T (vertical) axis
+pub const FLAG_CLAMP_T: u32 = 0x0008;
+/// Texture is a normal map
+pub const FLAG_NORMAL: u32 = 0x0080;
+/// Texture has no mipmaps
+pub const FLAG_NO_MIP: u32 = 0x0100;
+/// Texture is not affected by the texture detail setting
+pub const FLAG_NO_LOD: u32 = 0x0200;
+/// Texture has 1-bit (cut-out) transparency
+const FLAG_ONE_BIT_ALPHA: u32 = 0x1000;
+/// Texture has 8-bit transparency
+const FLAG_EIGHT_BIT_ALPHA: u32 = 0x2000;
+
+const HEADER_SIZE: u32 = 80;
+/// Largest size of the low-resolution image, as used by VTFLib
+const THUMBNAIL_SIZE: usize = 16;
+
+/// Pixel encodings supported by the VTF writer
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(unused)]    // Not every format is used by the converter
+pub enum ImageFormat {
+    /// Uncompressed, with alpha
+    Rgba8888,
+    /// Uncompressed, without alpha
+    Bgr888,
+    /// Compressed, without alpha
+    Dxt1,
+    /// Compressed, with alpha
+    Dxt5,
+}
+
+impl ImageFormat {
+    /// Picks DXT5 for images with transparency, and DXT1 for those without
+    pub fn compressed_for(rgba: &[u8]) -> ImageFormat {
+        if has_alpha(rgba) {
+            ImageFormat::Dxt5
+        } else {
+            ImageFormat::Dxt1
+        }
+    }
+
+    /// Numeric id of this format in the VTF header
+    fn id(self) -> u32 {
+        match self {
+            ImageFormat::Rgba8888 => 0,
+            ImageFormat::Bgr888 => 3,
+            ImageFormat::Dxt1 => 13,
+            ImageFormat::Dxt5 => 15,
+        }
+    }
+
+    fn from_id(id: u32) -> Option<ImageFormat> {
+        match id {
+            0 => Some(ImageFormat::Rgba8888),
+            3 => Some(ImageFormat::Bgr888),
+            12 => None,     // BGRA8888 is decoded separately, as it is only read and never written
+            13 => Some(ImageFormat::Dxt1),
+            15 => Some(ImageFormat::Dxt5),
+            _ => None
+        }
+    }
+
+    /// Size in bytes of an image in this format
+    fn data_size(self, width: usize, height: usize) -> usize {
+        match self {
+            ImageFormat::Rgba8888 => width * height * 4,
+            ImageFormat::Bgr888 => width * height * 3,
+            ImageFormat::Dxt1 => width.div_ceil(4) * height.div_ceil(4) * 8,
+            ImageFormat::Dxt5 => width.div_ceil(4) * height.div_ceil(4) * 16,
+        }
+    }
+
+    /// Encodes an RGBA image in this format
+    fn encode(self, width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+        match self {
+            ImageFormat::Rgba8888 => rgba.to_vec(),
+            ImageFormat::Bgr888 => rgba.chunks_exact(4).flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]).collect(),
+            ImageFormat::Dxt1 => encode_blocks(width, height, rgba, encode_color_block),
+            ImageFormat::Dxt5 => encode_blocks(width, height, rgba, |block, out| {
+                encode_alpha_block(block, out);
+                encode_color_block(block, out);
+            }),
+        }
+    }
+
+    /// Decodes an image in this format to RGBA
+    fn decode(self, width: usize, height: usize, data: &[u8]) -> Vec<u8> {
+        match self {
+            ImageFormat::Rgba8888 => data.to_vec(),
+            ImageFormat::Bgr888 => data.chunks_exact(3).flat_map(|pixel| [pixel[2], pixel[1], pixel[0], 255]).collect(),
+            ImageFormat::Dxt1 => decode_blocks(width, height, data, 8, decode_color_block),
+            ImageFormat::Dxt5 => decode_blocks(width, height, data, 16, |block, pixels| {
+                decode_color_block(&block[8..16], pixels);
+                decode_alpha_block(&block[0..8], pixels);
+            }),
+        }
+    }
+}
+
+/// Encodes an RGBA image into VTF 7.2 texture bytes
+/// Mipmaps are generated unless `FLAG_NO_MIP` is set, and a DXT1 low-resolution thumbnail is always included.
+/// Source engine requires both dimensions to be powers of two.
+pub fn encode_vtf(width: u16, height: u16, rgba: &[u8], format: ImageFormat, flags: u32) -> Vec<u8> {
+    debug_assert_eq!(rgba.len(), width as usize * height as usize * 4);
+
+    // Mipmap chain, from full size to 1x1
+    let mut mipmaps = vec![(width as usize, height as usize, rgba.to_vec())];
+    while let Some((width, height, image)) = mipmaps.last().filter(|(width, height, _)| *width > 1 || *height > 1) {
+        let mipmap = downsample(*width, *height, image);
+        mipmaps.push(mipmap);
+    }
+    let (thumbnail_width, thumbnail_height, thumbnail) = mipmaps.iter()
+        .find(|(width, height, _)| *width <= THUMBNAIL_SIZE && *height <= THUMBNAIL_SIZE)
+        .cloned()
+        .expect("mipmap chain ends at 1x1");
+
+    if flags & FLAG_NO_MIP != 0 {
+        mipmaps.truncate(1);
+    }
+
+    let mut flags = flags & !(FLAG_ONE_BIT_ALPHA | FLAG_EIGHT_BIT_ALPHA);   // Alpha flags are derived from the image
+    if has_alpha(rgba) {
+        match format {
+            ImageFormat::Rgba8888 | ImageFormat::Dxt5 => flags |= FLAG_EIGHT_BIT_ALPHA,
+            ImageFormat::Bgr888 | ImageFormat::Dxt1 => {}
+        }
+    }
+    if flags & FLAG_EIGHT_BIT_ALPHA != 0 && rgba.chunks_exact(4).all(|pixel| pixel[3] == 0 || pixel[3] == 255) {
+        flags = (flags & !FLAG_EIGHT_BIT_ALPHA) | FLAG_ONE_BIT_ALPHA;
+    }
+
+    let mut vtf = Vec::with_capacity(HEADER_SIZE as usize + rgba.len() * 2);
+    vtf.extend_from_slice(b"VTF\0");
+    vtf.extend_from_slice(&7u32.to_le_bytes());  // Version 7.2
+    vtf.extend_from_slice(&2u32.to_le_bytes());
+    vtf.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    vtf.extend_from_slice(&width.to_le_bytes());
+    vtf.extend_from_slice(&height.to_le_bytes());
+    vtf.extend_from_slice(&flags.to_le_bytes());
+    vtf.extend_from_slice(&1u16.to_le_bytes()); // Frame count
+    vtf.extend_from_slice(&0u16.to_le_bytes()); // First frame
+    vtf.extend_from_slice(&[0; 4]);
+    for channel in average_color(rgba) {    // Reflectivity
+        vtf.extend_from_slice(&channel.to_le_bytes());
+    }
+    vtf.extend_from_slice(&[0; 4]);
+    vtf.extend_from_slice(&1.0f32.to_le_bytes());   // Bumpmap scale
+    vtf.extend_from_slice(&format.id().to_le_bytes());
+    vtf.push(mipmaps.len() as u8);
+    vtf.extend_from_slice(&ImageFormat::Dxt1.id().to_le_bytes());   // Low-resolution image
+    vtf.push(thumbnail_width as u8);
+    vtf.push(thumbnail_height as u8);
+    vtf.extend_from_slice(&1u16.to_le_bytes()); // Depth
+    vtf.resize(HEADER_SIZE as usize, 0);
+
+    vtf.extend(ImageFormat::Dxt1.encode(thumbnail_width, thumbnail_height, &thumbnail));
+    for (width, height, image) in mipmaps.iter().rev() {    // Mipmaps are stored smallest first
+        vtf.extend(format.encode(*width, *height, image));
+    }
+    vtf
+}
+
+/// Writes an RGBA image as a VTF 7.2 texture, see [encode_vtf]
+pub fn write_vtf<W: Write + ?Sized>(out: &mut W, width: u16, height: u16, rgba: &[u8], format: ImageFormat, flags: u32) -> std::io::Result<()> {
+    out.write_all(&encode_vtf(width, height, rgba, format, flags))
+}
+
+/// Decodes the full-size image of a single-frame VTF texture, returning its width, height, flags, and RGBA pixels
+/// Supports the formats written by [encode_vtf], as well as BGRA8888.
+pub fn decode_vtf(vtf: &[u8]) -> Option<(u16, u16, u32, Vec<u8>)> {
+    if vtf.len() < HEADER_SIZE as usize || &vtf[0..4] != b"VTF\0" {
+        return None;
+    }
+    let width = u16::from_le_bytes([vtf[16], vtf[17]]);
+    let height = u16::from_le_bytes([vtf[18], vtf[19]]);
+    let flags = u32::from_le_bytes(vtf[20..24].try_into().ok()?);
+    let format_id = u32::from_le_bytes(vtf[52..56].try_into().ok()?);
+    let (width_usize, height_usize) = (width as usize, height as usize);
+
+    // Mipmaps are stored smallest first, so the full-size image is at the end of the file
+    let rgba = if format_id == 12 {
+        let data = vtf.get(vtf.len().checked_sub(width_usize * height_usize * 4)?..)?;
+        data.chunks_exact(4).flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]).collect()
+    } else {
+        let format = ImageFormat::from_id(format_id)?;
+        let data = vtf.get(vtf.len().checked_sub(format.data_size(width_usize, height_usize))?..)?;
+        format.decode(width_usize, height_usize, data)
+    };
+    Some((width, height, flags, rgba))
+}
+
+/// Returns true if any pixel of an RGBA image is not fully opaque
+fn has_alpha(rgba: &[u8]) -> bool {
+    rgba.chunks_exact(4).any(|pixel| pixel[3] != 255)
+}
+
+/// Average linear colour of an RGBA image, used as the texture's reflectivity
+fn average_color(rgba: &[u8]) -> [f32; 3] {
+    let pixel_count = (rgba.len() / 4).max(1) as f64;
+    let mut sum = [0.0f64; 3];
+    for pixel in rgba.chunks_exact(4) {
+        for (sum, channel) in sum.iter_mut().zip(pixel) {
+            *sum += (*channel as f64 / 255.0).powf(2.2);
+        }
+    }
+    sum.map(|channel| (channel / pixel_count) as f32)
+}
+
+/// Halves an RGBA image in size with a box filter, returning the new width, height, and pixels
+fn downsample(width: usize, height: usize, rgba: &[u8]) -> (usize, usize, Vec<u8>) {
+    let (new_width, new_height) = ((width / 2).max(1), (height / 2).max(1));
+    let mut image = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let samples = [(x * 2, y * 2), (x * 2 + 1, y * 2), (x * 2, y * 2 + 1), (x * 2 + 1, y * 2 + 1)]
+                .map(|(x, y)| (x.min(width - 1) + y.min(height - 1) * width) * 4);
+            for channel in 0..4 {
+                let sum = samples.iter().map(|index| rgba[index + channel] as u32).sum::<u32>();
+                image.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    (new_width, new_height, image)
+}
+
+/// Splits an RGBA image into 4x4 blocks (repeating edge pixels for images smaller than a block), and encodes each
+fn encode_blocks(width: usize, height: usize, rgba: &[u8], encode_block: impl Fn(&[[u8; 4]; 16], &mut Vec<u8>)) -> Vec<u8> {
+    let mut out = Vec::new();
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let block: [[u8; 4]; 16] = std::array::from_fn(|index| {
+                let x = (block_x + index % 4).min(width - 1);
+                let y = (block_y + index / 4).min(height - 1);
+                let pixel = (x + y * width) * 4;
+                [rgba[pixel], rgba[pixel + 1], rgba[pixel + 2], rgba[pixel + 3]]
+            });
+            encode_block(&block, &mut out);
+        }
+    }
+    out
+}
+
+/// Encodes the colour of a 4x4 block as DXT1, using the block's bounding box as endpoints
+fn encode_color_block(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for pixel in block {
+        for channel in 0..3 {
+            min[channel] = min[channel].min(pixel[channel]);
+            max[channel] = max[channel].max(pixel[channel]);
+        }
+    }
+
+    let (color_0, color_1) = (to_rgb565(max), to_rgb565(min));
+    out.extend_from_slice(&color_0.to_le_bytes());
+    out.extend_from_slice(&color_1.to_le_bytes());
+    if color_0 == color_1 {     // Single colour block, the 3-colour mode would otherwise be selected
+        out.extend_from_slice(&[0; 4]);
+        return;
+    }
+
+    let (max, min) = (from_rgb565(color_0), from_rgb565(color_1));
+    let palette: [[i32; 3]; 4] = [
+        max,
+        min,
+        std::array::from_fn(|channel| (max[channel] * 2 + min[channel]) / 3),
+        std::array::from_fn(|channel| (max[channel] + min[channel] * 2) / 3),
+    ];
+    let mut indices = 0u32;
+    for (index, pixel) in block.iter().enumerate() {
+        let nearest = (0..4).min_by_key(|entry| {
+            palette[*entry].iter().zip(pixel).map(|(entry, channel)| (entry - *channel as i32).pow(2)).sum::<i32>()
+        }).unwrap();
+        indices |= (nearest as u32) << (index * 2);
+    }
+    out.extend_from_slice(&indices.to_le_bytes());
+}
+
+/// Encodes the alpha of a 4x4 block as DXT5, using the block's alpha range as endpoints
+fn encode_alpha_block(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let alpha_0 = block.iter().map(|pixel| pixel[3]).max().unwrap();
+    let alpha_1 = block.iter().map(|pixel| pixel[3]).min().unwrap();
+    out.push(alpha_0);
+    out.push(alpha_1);
+    if alpha_0 == alpha_1 {
+        out.extend_from_slice(&[0; 6]);
+        return;
+    }
+
+    let palette: [i32; 8] = std::array::from_fn(|index| match index {
+        0 => alpha_0 as i32,
+        1 => alpha_1 as i32,
+        _ => ((8 - index as i32) * alpha_0 as i32 + (index as i32 - 1) * alpha_1 as i32) / 7
+    });
+    let mut indices = 0u64;
+    for (index, pixel) in block.iter().enumerate() {
+        let nearest = (0..8).min_by_key(|entry| (palette[*entry] - pixel[3] as i32).abs()).unwrap();
+        indices |= (nearest as u64) << (index * 3);
+    }
+    out.extend_from_slice(&indices.to_le_bytes()[0..6]);
+}
+
+/// Decodes an image of 4x4 blocks, each `block_size` bytes
+fn decode_blocks(width: usize, height: usize, data: &[u8], block_size: usize, decode_block: impl Fn(&[u8], &mut [[u8; 4]; 16])) -> Vec<u8> {
+    let mut rgba = vec![0; width * height * 4];
+    let mut blocks = data.chunks_exact(block_size);
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let mut pixels = [[0, 0, 0, 255]; 16];
+            if let Some(block) = blocks.next() {
+                decode_block(block, &mut pixels);
+            }
+            for (index, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (block_x + index % 4, block_y + index / 4);
+                if x < width && y < height {
+                    rgba[(x + y * width) * 4..][..4].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+    rgba
+}
+
+/// Decodes the colour of a DXT1 block, including 1-bit transparency
+fn decode_color_block(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    let color_0 = u16::from_le_bytes([block[0], block[1]]);
+    let color_1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let (a, b) = (from_rgb565(color_0), from_rgb565(color_1));
+    let palette: [[i32; 4]; 4] = if color_0 > color_1 {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            [(a[0] * 2 + b[0]) / 3, (a[1] * 2 + b[1]) / 3, (a[2] * 2 + b[2]) / 3, 255],
+            [(a[0] + b[0] * 2) / 3, (a[1] + b[1] * 2) / 3, (a[2] + b[2] * 2) / 3, 255],
+        ]
+    } else {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            [(a[0] + b[0]) / 2, (a[1] + b[1]) / 2, (a[2] + b[2]) / 2, 255],
+            [0, 0, 0, 0],
+        ]
+    };
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (index * 2)) as usize & 0b11].map(|channel| channel as u8);
+    }
+}
+
+/// Decodes the alpha of a DXT5 block
+fn decode_alpha_block(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    let (alpha_0, alpha_1) = (block[0] as i32, block[1] as i32);
+    let palette: [i32; 8] = std::array::from_fn(|index| match index {
+        0 => alpha_0,
+        1 => alpha_1,
+        _ if alpha_0 > alpha_1 => ((8 - index as i32) * alpha_0 + (index as i32 - 1) * alpha_1) / 7,
+        6 => 0,
+        7 => 255,
+        _ => ((6 - index as i32) * alpha_0 + (index as i32 - 1) * alpha_1) / 5
+    });
+    let mut index_bytes = [0; 8];
+    index_bytes[0..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        pixel[3] = palette[(indices >> (index * 3)) as usize & 0b111] as u8;
+    }
+}
+
+fn to_rgb565(color: [u8; 3]) -> u16 {
+    ((color[0] as u16 >> 3) << 11) | ((color[1] as u16 >> 2) << 5) | (color[2] as u16 >> 3)
+}
+
+fn from_rgb565(color: u16) -> [i32; 3] {
+    let (red, green, blue) = ((color >> 11) & 0x1F, (color >> 5) & 0x3F, color & 0x1F);
+    [(red << 3 | red >> 2) as i32, (green << 2 | green >> 4) as i32, (blue << 3 | blue >> 2) as i32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Image with a horizontal red gradient, a vertical green gradient, constant blue, and the given alpha
+    fn gradient(width: usize, height: usize, alpha: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| [(x * 255 / (width - 1)) as u8, (y * 255 / (height - 1)) as u8, 96, alpha(x, y)])
+            .collect()
+    }
+
+    /// Largest per-channel difference between two RGBA images
+    fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+        assert_eq!(a.len(), b.len());
+        a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        let rgba = gradient(16, 8, |x, _| (x * 16) as u8);
+        let (width, height, flags, decoded) = decode_vtf(&encode_vtf(16, 8, &rgba, ImageFormat::Rgba8888, FLAG_CLAMP_S)).unwrap();
+        assert_eq!((width, height), (16, 8));
+        assert_eq!(flags, FLAG_CLAMP_S | FLAG_EIGHT_BIT_ALPHA);
+        assert_eq!(decoded, rgba);
+
+        let rgba = gradient(8, 8, |_, _| 255);
+        let (_, _, flags, decoded) = decode_vtf(&encode_vtf(8, 8, &rgba, ImageFormat::Bgr888, 0)).unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        // DXT blocks interpolate between two colours, which cannot follow a two-dimensional gradient exactly
+        let rgba = gradient(32, 32, |_, _| 255);
+        let (_, _, _, decoded) = decode_vtf(&encode_vtf(32, 32, &rgba, ImageFormat::Dxt1, 0)).unwrap();
+        assert!(max_difference(&decoded, &rgba) <= 24, "DXT1 difference {}", max_difference(&decoded, &rgba));
+
+        let rgba = gradient(32, 32, |x, y| ((x + y) * 4) as u8);
+        let (_, _, flags, decoded) = decode_vtf(&encode_vtf(32, 32, &rgba, ImageFormat::Dxt5, 0)).unwrap();
+        assert_eq!(flags, FLAG_EIGHT_BIT_ALPHA);
+        assert!(max_difference(&decoded, &rgba) <= 24, "DXT5 difference {}", max_difference(&decoded, &rgba));
+    }
+
+    #[test]
+    fn solid_blocks_are_exact() {
+        // Colors representable in RGB565 survive DXT compression unchanged
+        let rgba = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]].into_iter()
+            .flat_map(|color| std::iter::repeat_n(color, 16))
+            .flatten()
+            .collect::<Vec<u8>>();
+        let (_, _, _, decoded) = decode_vtf(&encode_vtf(4, 16, &rgba, ImageFormat::Dxt1, 0)).unwrap();
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn alpha_flags() {
+        let cut_out = gradient(8, 8, |x, _| if x < 4 { 0 } else { 255 });
+        let vtf = encode_vtf(8, 8, &cut_out, ImageFormat::Dxt5, FLAG_EIGHT_BIT_ALPHA);
+        assert_eq!(decode_vtf(&vtf).unwrap().2, FLAG_ONE_BIT_ALPHA);
+
+        // Formats without alpha never get alpha flags
+        let vtf = encode_vtf(8, 8, &cut_out, ImageFormat::Dxt1, 0);
+        assert_eq!(decode_vtf(&vtf).unwrap().2, 0);
+    }
+
+    #[test]
+    fn mipmap_chain() {
+        let rgba = gradient(64, 16, |_, _| 255);
+        let thumbnail_size = ImageFormat::Dxt1.data_size(16, 4);
+
+        let vtf = encode_vtf(64, 16, &rgba, ImageFormat::Rgba8888, 0);
+        assert_eq!(vtf[56], 7, "mipmap count");     // 64x16 down to 1x1
+        let mipmap_size = [(64, 16), (32, 8), (16, 4), (8, 2), (4, 1), (2, 1), (1, 1)].iter().map(|(width, height)| width * height * 4).sum::<usize>();
+        assert_eq!(vtf.len(), HEADER_SIZE as usize + thumbnail_size + mipmap_size);
+
+        let vtf = encode_vtf(64, 16, &rgba, ImageFormat::Rgba8888, FLAG_NO_MIP);
+        assert_eq!(vtf[56], 1, "mipmap count");
+        assert_eq!(vtf.len(), HEADER_SIZE as usize + thumbnail_size + rgba.len());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert_eq!(decode_vtf(b"VTF\0"), None);
+        let mut vtf = encode_vtf(4, 4, &[255; 64], ImageFormat::Rgba8888, 0);
+        vtf[0..4].copy_from_slice(b"PNG\0");
+        assert_eq!(decode_vtf(&vtf), None);
+    }
+}